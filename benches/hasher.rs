@@ -0,0 +1,61 @@
+//! Throughput of loading a bview with the default `RandomState` hasher and with `ahash`.
+use std::hint::black_box;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bgpkit_parser::encoder::MrtRibEncoder;
+use bgpkit_parser::models::{AsPath, BgpElem, ElemType, NetworkPrefix};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use ipnet::{IpNet, Ipv4Net};
+use mrt_state_to_state::mrt_processor::MrtProcessor;
+
+/// Prefixes per peer, about a tenth of a full IPv4 table
+const PREFIXES: u32 = 100_000;
+const PEERS: u8 = 4;
+
+// Write a bview in which every peer announces the same `PREFIXES` /24 prefixes
+fn write_bview() -> PathBuf {
+    let mut encoder = MrtRibEncoder::new();
+    for peer in 1..=PEERS {
+        let peer_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, peer));
+        let peer_asn = 65000 + u32::from(peer);
+        for i in 0..PREFIXES {
+            let network = Ipv4Addr::from((10 << 24) | (i << 8));
+            encoder.process_elem(&BgpElem {
+                timestamp: 1600000000.0,
+                elem_type: ElemType::ANNOUNCE,
+                peer_ip,
+                peer_asn: peer_asn.into(),
+                prefix: NetworkPrefix::new(IpNet::V4(Ipv4Net::new(network, 24).unwrap()), 0),
+                next_hop: Some(peer_ip),
+                as_path: Some(AsPath::from_sequence([peer_asn, 64500 + i % 100])),
+                ..Default::default()
+            });
+        }
+    }
+    let path = std::env::temp_dir().join(format!("mrt_state_to_state_bench_bview_{}.mrt", std::process::id()));
+    std::fs::write(&path, encoder.export_bytes()).unwrap();
+    path
+}
+
+fn bench_process_bview(c: &mut Criterion) {
+    let path = write_bview();
+    let mut group = c.benchmark_group("process_bview");
+    group.sample_size(10).measurement_time(Duration::from_secs(10));
+    group.throughput(Throughput::Elements(u64::from(PREFIXES) * u64::from(PEERS)));
+
+    group.bench_function("random_state", |b| b.iter(|| load(MrtProcessor::new(180, None), &path)));
+    group.bench_function("ahash", |b| b.iter(|| load(MrtProcessor::with_ahash(180, None), &path)));
+
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn load<S: std::hash::BuildHasher + Default + Clone>(mut processor: MrtProcessor<S>, path: &Path) -> MrtProcessor<S> {
+    processor.process_bview(path).unwrap();
+    black_box(processor)
+}
+
+criterion_group!(benches, bench_process_bview);
+criterion_main!(benches);
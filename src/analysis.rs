@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use bgpkit_parser::models::{Community, MetaCommunity, NetworkPrefix};
+
+use crate::bgp_state::{Announcement, CommunityKey};
+use crate::mrt_records::format_community;
+
+/// Number of communities kept in `CommunityPolicySummary::dominant_communities`
+pub const DOMINANT_COMMUNITIES: usize = 10;
+
+/// Routing policy of a peer as far as it can be inferred from the communities on its announcements
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommunityPolicySummary {
+    /// The most frequent communities with the number of prefixes carrying them, most frequent first
+    pub dominant_communities: Vec<(MetaCommunity, usize)>,
+    /// Distinct communities per (prefix, community) pair, low values mean a few communities are set on many prefixes
+    pub community_diversity: f64,
+    /// `AS<asn>` of the network whose communities are set on the most prefixes, e.g. `AS3356` for Lumen
+    pub likely_operator: Option<String>,
+}
+
+/// Summarize the communities of the announcements of a peer.
+///
+/// Communities repeated on an announcement are counted once. The likely operator is taken from the ASN part of
+/// standard and large communities, the reserved ranges 0 and 65535 (RFC 1997) are ignored.
+pub fn infer_community_policy<S: BuildHasher>(announcements: &HashMap<NetworkPrefix, Announcement, S>) -> CommunityPolicySummary {
+    let mut prefix_counts: HashMap<CommunityKey, usize> = HashMap::new();
+    let mut operator_counts: HashMap<u32, usize> = HashMap::new();
+    let mut pairs = 0;
+
+    for ann in announcements.values() {
+        let mut seen: Vec<CommunityKey> = Vec::new();
+        for community in ann.communities.iter().flatten() {
+            let key = CommunityKey(*community);
+            if seen.contains(&key) {
+                continue;
+            }
+            pairs += 1;
+            if let Some(asn) = community_asn(community) {
+                *operator_counts.entry(asn).or_insert(0) += 1;
+            }
+            *prefix_counts.entry(key).or_insert(0) += 1;
+            seen.push(key);
+        }
+    }
+
+    let community_diversity = if pairs == 0 { 0.0 } else { prefix_counts.len() as f64 / pairs as f64 };
+    let likely_operator = operator_counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .map(|(asn, _)| format!("AS{}", asn));
+
+    // Ties are ordered by the formatted community, which is only built once per distinct community
+    let mut dominant: Vec<(MetaCommunity, usize)> = prefix_counts.into_iter().map(|(key, count)| (key.0, count)).collect();
+    dominant.sort_by_cached_key(|(community, count)| (std::cmp::Reverse(*count), format_community(community)));
+    dominant.truncate(DOMINANT_COMMUNITIES);
+
+    CommunityPolicySummary {
+        dominant_communities: dominant,
+        community_diversity,
+        likely_operator,
+    }
+}
+
+// ASN that defined the community, `None` for extended and reserved communities
+fn community_asn(community: &MetaCommunity) -> Option<u32> {
+    let asn = match community {
+        MetaCommunity::Plain(Community::Custom(asn, _)) => asn.to_u32(),
+        MetaCommunity::Large(large) => large.global_admin,
+        _ => return None,
+    };
+    (asn != 0 && asn != 65535).then_some(asn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgpkit_parser::models::{BgpElem, LargeCommunity};
+    use std::str::FromStr;
+
+    fn announcement(communities: Vec<MetaCommunity>) -> Announcement {
+        Announcement::from_bgp_elem(BgpElem { timestamp: 1600000000.0, communities: Some(communities), ..Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_infer_community_policy() {
+        let lumen = |value| MetaCommunity::Plain(Community::Custom(3356.into(), value));
+        let announcements: HashMap<NetworkPrefix, Announcement> = [
+            ("198.51.100.0/24", vec![lumen(100), lumen(2001), lumen(100)]),
+            ("203.0.113.0/24", vec![lumen(100), MetaCommunity::Plain(Community::NoExport)]),
+            ("192.0.2.0/24", vec![lumen(100), MetaCommunity::Large(LargeCommunity::new(65001, [1, 2]))]),
+            ("2001:db8::/32", vec![]),
+        ]
+        .into_iter()
+        .map(|(prefix, communities)| (NetworkPrefix::from_str(prefix).unwrap(), announcement(communities)))
+        .collect();
+
+        let summary = infer_community_policy(&announcements);
+        assert_eq!(summary.dominant_communities[0], (lumen(100), 3));
+        assert_eq!(summary.dominant_communities.len(), 4);
+        assert!(summary.dominant_communities[1..].iter().all(|(_, count)| *count == 1));
+        // 4 distinct communities in 6 prefix and community pairs
+        assert_eq!(summary.community_diversity, 4.0 / 6.0);
+        assert_eq!(summary.likely_operator.as_deref(), Some("AS3356"));
+
+        let empty = infer_community_policy(&HashMap::<NetworkPrefix, Announcement>::new());
+        assert!(empty.dominant_communities.is_empty());
+        assert_eq!(empty.community_diversity, 0.0);
+        assert_eq!(empty.likely_operator, None);
+    }
+}
@@ -2,8 +2,9 @@ use std::{collections::HashMap, net::IpAddr};
 
 use bgpkit_parser::models::NetworkPrefix;
 use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
 struct PeerPrefix {
     peer_ip: IpAddr,
     prefix: NetworkPrefix,
@@ -20,8 +21,9 @@ impl PeerPrefix {
 
 
 
-#[derive(Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AnnouncementTracker {
+    #[serde(with = "crate::util::map_as_pairs")]
     announement_start: HashMap<PeerPrefix, NaiveDateTime>,
 }
 
@@ -36,6 +38,12 @@ impl AnnouncementTracker {
         initial
     }
 
+    pub fn announcement_start(&self, peer_ip: IpAddr, prefix: NetworkPrefix) -> Option<NaiveDateTime> {
+        self.announement_start
+            .get(&PeerPrefix::new(peer_ip, prefix))
+            .cloned()
+    }
+
     pub fn withdraw_announcement(&mut self, peer_ip: IpAddr, prefix: NetworkPrefix) -> Option<NaiveDateTime> {
         self.announement_start
             .remove(&PeerPrefix::new(peer_ip, prefix))
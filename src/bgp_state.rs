@@ -1,26 +1,303 @@
 use core::fmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash, Hasher, RandomState};
 use std::net::IpAddr;
-use bgpkit_parser::models::{AsPath, BgpElem, BgpOpenMessage, MetaCommunity, NetworkPrefix, OptParam, Origin};
-use chrono::{DateTime, Utc};
+use std::sync::OnceLock;
+use std::time::Duration;
+use bgpkit_parser::models::{AsPath, AttrType, BgpElem, BgpNotificationMessage, BgpOpenMessage, Community, ExtendedCommunity, MetaCommunity, NetworkPrefix, OptParam, Origin};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-/// Represents the state of a BGP connection
-#[derive(Debug, Clone)]
-pub struct BgpState {
+use crate::announcement::AnnouncementTracker;
+use crate::mrt_records::{as_path_origin, decode_as4_aggregator, decode_as4_path, find_raw_attribute};
+use crate::util::{attribute_hash, format_prefix, normalize_prefix, prefix_sort_key};
+
+/// Represents the state of a BGP connection.
+///
+/// `S` is the hasher of the per-prefix maps, see `MrtProcessor::with_ahash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(serialize = "", deserialize = "S: BuildHasher + Default"))]
+pub struct BgpState<S = RandomState> {
     /// The current state of the BGP connection (e.g. Established, Active, etc.)
-    pub connection_state: ConnectionState,
+    #[serde(with = "connection_state_code")]
+    connection_state: ConnectionState,
     /// Timestamp of the last received message
-    pub last_message_timestamp: Option<DateTime<Utc>>,
+    last_message_timestamp: Option<DateTime<Utc>>,
+    /// Earliest timestamp observed for the peer, kept across state changes
+    #[serde(default)]
+    first_seen: Option<DateTime<Utc>>,
+    /// Latest timestamp observed for the peer, kept across state changes
+    #[serde(default)]
+    last_seen: Option<DateTime<Utc>>,
     /// Map from IP prefix to the last announcement for that prefix
-    prefix_announcements: HashMap<NetworkPrefix, Announcement>,
+    #[serde(with = "crate::util::map_as_pairs")]
+    prefix_announcements: HashMap<NetworkPrefix, Announcement, S>,
+    /// Announcements of IPv4 and IPv6 VPN routes (SAFI 128 and 129), kept apart from the unicast prefixes
+    #[serde(default, with = "crate::util::map_as_pairs")]
+    vpn_announcements: HashMap<(RouteDistinguisher, NetworkPrefix), Announcement, S>,
+    /// Reverse index from community to the prefixes announced with it.
+    ///
+    /// Costs one set entry (a `NetworkPrefix`, about 24 bytes plus hash table overhead) per community of every
+    /// announcement. Not serialized, see `rebuild_community_index`.
+    #[serde(skip)]
+    community_index: HashMap<CommunityKey, HashSet<NetworkPrefix, S>, S>,
     /// Hold time from last open message
-    pub hold_time: Option<u16>,
+    hold_time: Option<u16>,
     /// BGP options
-    options: Option<Vec<OptParam>>
+    options: Option<Vec<OptParam>>,
+    /// Recent announcements and withdrawals, for detecting oscillating prefixes
+    #[serde(skip)]
+    burst_tracker: BurstTracker,
+    /// NOTIFICATION messages received from the peer
+    notifications: Vec<NotificationRecord>,
+    /// Withdrawals for prefixes that were not announced, e.g. because the announcement predates the first dump
+    #[serde(default)]
+    orphan_withdrawals: u64,
+    /// Announcement start times, only kept when enabled with `with_announcement_tracker`
+    #[serde(default)]
+    tracker: Option<AnnouncementTracker>,
+    /// Address of the peer, taken from the first tracked announcement
+    #[serde(default)]
+    tracked_peer_ip: Option<IpAddr>,
+    /// Recent connection state changes
+    #[serde(default)]
+    session_history: SessionHistory,
+    /// Highest number of announcements held at once, kept across session resets
+    #[serde(default)]
+    peak_prefix_count: usize,
+    /// Time of the last transition to Established, unset when the session is not established
+    #[serde(default)]
+    established_at: Option<DateTime<Utc>>,
+    /// (time since established, prefix count) every `CONVERGENCE_SAMPLE_INTERVAL` until the count is stable
+    #[serde(default)]
+    post_established_prefix_rates: Vec<(Duration, usize)>,
+}
+
+/// `MetaCommunity` as a map key, bgpkit_parser does not implement `Hash` for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CommunityKey(pub(crate) MetaCommunity);
+
+impl Hash for CommunityKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            MetaCommunity::Plain(community) => {
+                std::mem::discriminant(community).hash(state);
+                if let Community::Custom(asn, value) = community {
+                    (asn, value).hash(state);
+                }
+            },
+            MetaCommunity::Large(large) => (large.global_admin, large.local_data).hash(state),
+            MetaCommunity::Extended(extended) => {
+                std::mem::discriminant(extended).hash(state);
+                match extended {
+                    ExtendedCommunity::TransitiveTwoOctetAs(c) | ExtendedCommunity::NonTransitiveTwoOctetAs(c) => {
+                        (c.subtype, c.global_admin, c.local_admin).hash(state)
+                    },
+                    ExtendedCommunity::TransitiveIpv4Addr(c) | ExtendedCommunity::NonTransitiveIpv4Addr(c) => {
+                        (c.subtype, c.global_admin, c.local_admin).hash(state)
+                    },
+                    ExtendedCommunity::TransitiveFourOctetAs(c) | ExtendedCommunity::NonTransitiveFourOctetAs(c) => {
+                        (c.subtype, c.global_admin, c.local_admin).hash(state)
+                    },
+                    ExtendedCommunity::TransitiveOpaque(c) | ExtendedCommunity::NonTransitiveOpaque(c) => (c.subtype, c.value).hash(state),
+                    ExtendedCommunity::Raw(bytes) => bytes.hash(state),
+                }
+            },
+            MetaCommunity::Ipv6Extended(c) => (c.community_type, c.subtype, c.global_admin, c.local_admin).hash(state),
+        }
+    }
+}
+
+fn index_communities<S: BuildHasher + Default>(index: &mut HashMap<CommunityKey, HashSet<NetworkPrefix, S>, S>, prefix: NetworkPrefix, ann: &Announcement) {
+    for community in ann.communities.iter().flatten() {
+        index.entry(CommunityKey(*community)).or_default().insert(prefix);
+    }
+}
+
+fn unindex_communities<S: BuildHasher>(index: &mut HashMap<CommunityKey, HashSet<NetworkPrefix, S>, S>, prefix: &NetworkPrefix, ann: &Announcement) {
+    for community in ann.communities.iter().flatten() {
+        let key = CommunityKey(*community);
+        if let Some(prefixes) = index.get_mut(&key) {
+            prefixes.remove(prefix);
+            if prefixes.is_empty() {
+                index.remove(&key);
+            }
+        }
+    }
+}
+
+/// Interval between the prefix count samples after a session is established
+pub const CONVERGENCE_SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of samples after which a session that is still growing is no longer sampled (one hour)
+const MAX_CONVERGENCE_SAMPLES: usize = 120;
+
+/// Growth between two samples below which the prefix count is considered stable
+const CONVERGENCE_GROWTH_THRESHOLD: f64 = 0.01;
+
+/// Route distinguisher of a VPN route (RFC 4364), type field followed by the value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RouteDistinguisher(pub [u8; 8]);
+
+impl fmt::Display for RouteDistinguisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        match u16::from_be_bytes([b[0], b[1]]) {
+            0 => write!(f, "{}:{}", u16::from_be_bytes([b[2], b[3]]), u32::from_be_bytes([b[4], b[5], b[6], b[7]])),
+            1 => write!(f, "{}.{}.{}.{}:{}", b[2], b[3], b[4], b[5], u16::from_be_bytes([b[6], b[7]])),
+            2 => write!(f, "{}:{}", u32::from_be_bytes([b[2], b[3], b[4], b[5]]), u16::from_be_bytes([b[6], b[7]])),
+            _ => write!(f, "{}", b.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+        }
+    }
+}
+
+/// Default number of connection state changes kept per peer
+pub const DEFAULT_MAX_SESSION_HISTORY: usize = 100;
+
+/// A change of the connection state of a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionEvent {
+    pub timestamp: DateTime<Utc>,
+    #[serde(with = "connection_state_code")]
+    pub from: ConnectionState,
+    #[serde(with = "connection_state_code")]
+    pub to: ConnectionState,
+}
+
+/// The most recent connection state changes of a peer, the oldest are evicted when `max_entries` is reached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionHistory {
+    events: VecDeque<SessionEvent>,
+    max_entries: usize,
+}
+
+impl Default for SessionHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SESSION_HISTORY)
+    }
+}
+
+impl SessionHistory {
+    pub fn new(max_entries: usize) -> Self {
+        SessionHistory {
+            events: VecDeque::with_capacity(max_entries.min(DEFAULT_MAX_SESSION_HISTORY)),
+            max_entries,
+        }
+    }
+
+    pub fn push(&mut self, event: SessionEvent) {
+        if self.max_entries == 0 {
+            return;
+        }
+        if self.events.len() == self.max_entries {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// The events in chronological order
+    pub fn iter(&self) -> impl Iterator<Item = &SessionEvent> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Number of sessions that went down (Established to another state) in the recorded history
+    pub fn flap_count(&self) -> u32 {
+        self.events.iter().filter(|event| is_flap(event)).count() as u32
+    }
+
+    /// Time of the last session that went down
+    pub fn last_flap(&self) -> Option<DateTime<Utc>> {
+        self.events.iter().rev().find(|event| is_flap(event)).map(|event| event.timestamp)
+    }
+
+    /// Time between sessions going down and the next Established up to `as_of`, only periods after a recorded flap
+    /// count
+    pub fn total_downtime(&self, as_of: DateTime<Utc>) -> chrono::Duration {
+        let mut total = chrono::Duration::zero();
+        let mut down_since = None;
+        for event in self.events.iter().take_while(|event| event.timestamp <= as_of) {
+            if is_flap(event) {
+                down_since = Some(event.timestamp);
+            } else if event.to == ConnectionState::Established && let Some(since) = down_since.take() {
+                total += event.timestamp - since;
+            }
+        }
+        total + down_since.map_or(chrono::Duration::zero(), |since| as_of - since)
+    }
+
+    /// Number of sessions that went down (Established to another state) in the `window` before `as_of`
+    pub fn flap_count_in_window(&self, window: chrono::Duration, as_of: DateTime<Utc>) -> u32 {
+        let window_start = as_of - window;
+        self.events.iter()
+            .rev()
+            .skip_while(|event| event.timestamp > as_of)
+            .take_while(|event| event.timestamp >= window_start)
+            .filter(|event| is_flap(event))
+            .count() as u32
+    }
+}
+
+// A session going down
+fn is_flap(event: &SessionEvent) -> bool {
+    event.from == ConnectionState::Established && event.to != ConnectionState::Established
+}
+
+/// Length of the sliding window used for counting update bursts
+const BURST_WINDOW_SECONDS: i64 = 60;
+
+/// Tracks announce/withdraw events per prefix within a sliding window
+#[derive(Debug, Clone, Default)]
+pub struct BurstTracker {
+    events: VecDeque<(DateTime<Utc>, NetworkPrefix)>,
+}
+
+impl BurstTracker {
+    /// Record an announcement or withdrawal for a prefix
+    pub fn record(&mut self, ts: DateTime<Utc>, prefix: NetworkPrefix) {
+        self.events.push_back((ts, prefix));
+        self.evict(ts);
+    }
+
+    /// Drop events that are older than the window relative to `as_of`
+    pub fn evict(&mut self, as_of: DateTime<Utc>) {
+        let window_start = as_of - chrono::Duration::seconds(BURST_WINDOW_SECONDS);
+        while self.events.front().is_some_and(|(ts, _)| *ts < window_start) {
+            self.events.pop_front();
+        }
+    }
+
+    /// Number of events per prefix in the window ending at `as_of`
+    pub fn counts(&self, as_of: DateTime<Utc>) -> HashMap<NetworkPrefix, u32> {
+        let window_start = as_of - chrono::Duration::seconds(BURST_WINDOW_SECONDS);
+        let mut counts = HashMap::new();
+        for (ts, prefix) in &self.events {
+            if *ts >= window_start && *ts <= as_of {
+                *counts.entry(*prefix).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
 }
 
 /// Represents the possible states of a BGP connection
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ConnectionState {
     Idle,
     Connect,
@@ -30,6 +307,82 @@ pub enum ConnectionState {
     Established,
 }
 
+impl ConnectionState {
+    /// Compact representation: Idle=0, Connect=1, Active=2, OpenSent=3, OpenConfirm=4, Established=5
+    pub const fn to_u8(&self) -> u8 {
+        match self {
+            ConnectionState::Idle => 0,
+            ConnectionState::Connect => 1,
+            ConnectionState::Active => 2,
+            ConnectionState::OpenSent => 3,
+            ConnectionState::OpenConfirm => 4,
+            ConnectionState::Established => 5,
+        }
+    }
+
+    /// Inverse of `to_u8`, `None` for values that are not a connection state
+    pub const fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(ConnectionState::Idle),
+            1 => Some(ConnectionState::Connect),
+            2 => Some(ConnectionState::Active),
+            3 => Some(ConnectionState::OpenSent),
+            4 => Some(ConnectionState::OpenConfirm),
+            5 => Some(ConnectionState::Established),
+            _ => None,
+        }
+    }
+}
+
+/// Serde representation of a `ConnectionState` as its `to_u8` code, used for the state in checkpoints and
+/// `MrtProcessor::save_state_json`. The variant name is still accepted when reading, e.g. from older state files.
+mod connection_state_code {
+    use core::fmt;
+
+    use serde::de::{self, Unexpected, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    use super::ConnectionState;
+
+    pub fn serialize<S: Serializer>(state: &ConnectionState, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(state.to_u8())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ConnectionState, D::Error> {
+        deserializer.deserialize_any(CodeVisitor)
+    }
+
+    struct CodeVisitor;
+
+    impl Visitor<'_> for CodeVisitor {
+        type Value = ConnectionState;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a connection state code between 0 and 5 or a connection state name")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<ConnectionState, E> {
+            u8::try_from(v).ok().and_then(ConnectionState::from_u8).ok_or_else(|| E::invalid_value(Unexpected::Unsigned(v), &self))
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<ConnectionState, E> {
+            u8::try_from(v).ok().and_then(ConnectionState::from_u8).ok_or_else(|| E::invalid_value(Unexpected::Signed(v), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<ConnectionState, E> {
+            match v {
+                "Idle" => Ok(ConnectionState::Idle),
+                "Connect" => Ok(ConnectionState::Connect),
+                "Active" => Ok(ConnectionState::Active),
+                "OpenSent" => Ok(ConnectionState::OpenSent),
+                "OpenConfirm" => Ok(ConnectionState::OpenConfirm),
+                "Established" => Ok(ConnectionState::Established),
+                _ => Err(E::invalid_value(Unexpected::Str(v), &self)),
+            }
+        }
+    }
+}
+
 impl fmt::Display for ConnectionState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -48,21 +401,26 @@ pub trait BgpKitStateExt {
     fn to_connection_state(&self) -> ConnectionState;
 }
 
+// The MRT state values are the `to_u8` values plus one
+const _: () = {
+    use bgpkit_parser::models::BgpState as MrtState;
+    assert!(MrtState::Idle as u16 == ConnectionState::Idle.to_u8() as u16 + 1);
+    assert!(MrtState::Connect as u16 == ConnectionState::Connect.to_u8() as u16 + 1);
+    assert!(MrtState::Active as u16 == ConnectionState::Active.to_u8() as u16 + 1);
+    assert!(MrtState::OpenSent as u16 == ConnectionState::OpenSent.to_u8() as u16 + 1);
+    assert!(MrtState::OpenConfirm as u16 == ConnectionState::OpenConfirm.to_u8() as u16 + 1);
+    assert!(MrtState::Established as u16 == ConnectionState::Established.to_u8() as u16 + 1);
+};
+
 impl BgpKitStateExt for bgpkit_parser::models::BgpState {
     fn to_connection_state(&self) -> ConnectionState {
-        match self {
-            bgpkit_parser::models::BgpState::Idle => ConnectionState::Idle,
-            bgpkit_parser::models::BgpState::Connect => ConnectionState::Connect,
-            bgpkit_parser::models::BgpState::Active => ConnectionState::Active,
-            bgpkit_parser::models::BgpState::OpenSent => ConnectionState::OpenSent,
-            bgpkit_parser::models::BgpState::OpenConfirm => ConnectionState::OpenConfirm,
-            bgpkit_parser::models::BgpState::Established => ConnectionState::Established,
-        }
+        // bgpkit_parser uses the MRT values (RFC 6396 §4.4.1), which start at 1
+        ConnectionState::from_u8((u16::from(*self) - 1) as u8).expect("bgpkit_parser BGP state out of range")
     }
 }
 
 /// Represents a BGP route announcement
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Announcement {
     /// Timestamp when the announcement was received
     pub timestamp: DateTime<Utc>,
@@ -81,6 +439,118 @@ pub struct Announcement {
     pub communities: Option<Vec<MetaCommunity>>,
 
     pub only_to_customer: Option<u32>,
+
+    /// Aggregator ASN and address
+    #[serde(default)]
+    pub aggregator: Option<(u32, IpAddr)>,
+
+    /// Cached `attribute_hash`, computed on first use
+    #[serde(skip)]
+    attribute_hash: OnceLock<u64>,
+}
+
+/// 2-byte placeholder for 4-byte ASNs (RFC 6793)
+pub const AS_TRANS: u32 = 23456;
+
+/// Prefixes that differ between two states of a peer, see `BgpState::diff`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BgpStateDiff {
+    /// Prefixes only announced in the new state
+    pub added: Vec<NetworkPrefix>,
+    /// Prefixes only announced in the old state
+    pub removed: Vec<NetworkPrefix>,
+    /// Prefixes announced in both states with different route attributes
+    pub changed: Vec<NetworkPrefix>,
+}
+
+impl BgpStateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Distribution of the age of announcements, in seconds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AgeDistribution {
+    pub min_seconds: u64,
+    pub max_seconds: u64,
+    pub mean_seconds: f64,
+    pub p50_seconds: u64,
+    pub p95_seconds: u64,
+    pub p99_seconds: u64,
+    pub total_prefixes: usize,
+}
+
+impl AgeDistribution {
+    /// Compute the distribution from a list of ages, all fields are 0 for an empty list
+    pub fn from_ages(mut ages: Vec<u64>) -> Self {
+        if ages.is_empty() {
+            return AgeDistribution::default();
+        }
+        ages.sort_unstable();
+
+        // Nearest-rank percentile
+        let percentile = |p: f64| ages[((p / 100.0 * ages.len() as f64).ceil() as usize).clamp(1, ages.len()) - 1];
+
+        AgeDistribution {
+            min_seconds: ages[0],
+            max_seconds: ages[ages.len() - 1],
+            mean_seconds: ages.iter().sum::<u64>() as f64 / ages.len() as f64,
+            p50_seconds: percentile(50.0),
+            p95_seconds: percentile(95.0),
+            p99_seconds: percentile(99.0),
+            total_prefixes: ages.len(),
+        }
+    }
+}
+
+/// Error code of a Cease NOTIFICATION
+const CEASE_ERROR_CODE: u8 = 6;
+/// Cease subcodes that can carry a shutdown communication (RFC 8203 §2)
+const ADMINISTRATIVE_SHUTDOWN_SUBCODE: u8 = 2;
+const ADMINISTRATIVE_RESET_SUBCODE: u8 = 4;
+/// Maximum length of a shutdown communication in bytes (RFC 8203 §2)
+const MAX_SHUTDOWN_MESSAGE_LENGTH: usize = 128;
+
+/// A NOTIFICATION message received from a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationRecord {
+    /// Timestamp when the notification was received
+    pub timestamp: DateTime<Utc>,
+    pub error_code: u8,
+    pub error_subcode: u8,
+    /// Shutdown communication (RFC 8203) of an administrative shutdown or reset
+    pub shutdown_message: Option<String>,
+}
+
+impl NotificationRecord {
+    pub fn from_message(timestamp: DateTime<Utc>, msg: &BgpNotificationMessage) -> Self {
+        let (error_code, error_subcode) = msg.error.get_codes();
+        let shutdown_message = match (error_code, error_subcode) {
+            (CEASE_ERROR_CODE, ADMINISTRATIVE_SHUTDOWN_SUBCODE | ADMINISTRATIVE_RESET_SUBCODE) => parse_shutdown_communication(&msg.data),
+            _ => None,
+        };
+
+        NotificationRecord {
+            timestamp,
+            error_code,
+            error_subcode,
+            shutdown_message,
+        }
+    }
+}
+
+/// Parse the length-prefixed UTF-8 shutdown communication from the data of a Cease NOTIFICATION.
+///
+/// Messages are truncated at 128 bytes, an empty or missing message results in `None`.
+pub fn parse_shutdown_communication(data: &[u8]) -> Option<String> {
+    let (length, message) = data.split_first()?;
+    let length = (*length as usize).min(MAX_SHUTDOWN_MESSAGE_LENGTH).min(message.len());
+    if length == 0 {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&message[..length]).into_owned())
 }
 
 fn timestamp_to_datetime(timestamp: f64) -> DateTime<Utc> {
@@ -98,30 +568,125 @@ impl Announcement {
         // Convert timestamp from f64 to DateTime
         let timestamp = timestamp_to_datetime(elem.timestamp);
 
+        let mut as_path = elem.as_path.clone();
+        let mut aggregator = match (elem.aggr_asn, elem.aggr_ip) {
+            (Some(asn), Some(ip)) => Some((asn.to_u32(), IpAddr::V4(ip))),
+            _ => None,
+        };
+
+        // Merge AS4_PATH and AS4_AGGREGATOR when bgpkit_parser left them as unknown attributes
+        if let Some(unknown) = &elem.unknown {
+            match find_raw_attribute(unknown, AttrType::AS4_PATH).map(decode_as4_path) {
+                Some(Ok(as4_path)) => {
+                    as_path = Some(match &as_path {
+                        Some(path) => AsPath::merge_aspath_as4path(path, &as4_path),
+                        None => as4_path,
+                    });
+                },
+                Some(Err(e)) => log::warn!("Ignoring malformed AS4_PATH for {}: {}", format_prefix(&elem.prefix), e),
+                None => {},
+            }
+
+            // AS4_AGGREGATOR only applies when the AGGREGATOR carries AS_TRANS
+            if aggregator.is_none_or(|(asn, _)| asn == AS_TRANS) {
+                match find_raw_attribute(unknown, AttrType::AS4_AGGREGATOR).map(decode_as4_aggregator) {
+                    Some(Ok(as4_aggregator)) => aggregator = Some(as4_aggregator),
+                    Some(Err(e)) => log::warn!("Ignoring malformed AS4_AGGREGATOR for {}: {}", format_prefix(&elem.prefix), e),
+                    None => {},
+                }
+            }
+        }
+
         Ok(Announcement {
             timestamp,
-            as_path: elem.as_path.clone(),
+            as_path,
             origin: elem.origin,
             local_pref: elem.local_pref,
             next_hop: elem.next_hop,
             med: elem.med,
             communities: elem.communities.clone(),
             only_to_customer: elem.only_to_customer.map(|v| v.to_u32()),
+            aggregator,
+            attribute_hash: OnceLock::new(),
         })
     }
+
+    /// Hash of the route attributes, see `util::attribute_hash`.
+    ///
+    /// The hash is cached, so the attributes should not be modified after it was computed.
+    pub fn attribute_hash(&self) -> u64 {
+        *self.attribute_hash.get_or_init(|| attribute_hash(self))
+    }
+
+    /// Whether both announcements have the same route attributes.
+    ///
+    /// Different hashes rule out equality quickly, equal hashes are confirmed field by field.
+    pub fn same_attributes(&self, other: &Announcement) -> bool {
+        self.attribute_hash() == other.attribute_hash()
+            && self.next_hop == other.next_hop
+            && self.as_path == other.as_path
+            && self.communities == other.communities
+            && self.origin == other.origin
+            && self.local_pref == other.local_pref
+            && self.med == other.med
+    }
 }
 
-impl BgpState {
-    /// Creates a new BGP state with default values
-    pub fn new() -> Self {
+impl<S: BuildHasher + Default> Default for BgpState<S> {
+    fn default() -> Self {
         BgpState {
             connection_state: ConnectionState::Idle,
             last_message_timestamp: None,
-            prefix_announcements: HashMap::new(),
+            first_seen: None,
+            last_seen: None,
+            prefix_announcements: HashMap::default(),
+            vpn_announcements: HashMap::default(),
+            community_index: HashMap::default(),
             hold_time: None,
             options: None,
+            burst_tracker: BurstTracker::default(),
+            notifications: Vec::new(),
+            orphan_withdrawals: 0,
+            tracker: None,
+            tracked_peer_ip: None,
+            session_history: SessionHistory::default(),
+            peak_prefix_count: 0,
+            established_at: None,
+            post_established_prefix_rates: Vec::new(),
         }
     }
+}
+
+impl BgpState {
+    /// Creates a new BGP state with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S: BuildHasher + Default> BgpState<S> {
+    /// Keep at most `max_entries` connection state changes in the session history
+    pub fn with_max_session_history(mut self, max_entries: usize) -> Self {
+        self.session_history = SessionHistory::new(max_entries);
+        self
+    }
+
+    /// The most recent connection state changes
+    pub fn session_history(&self) -> &SessionHistory {
+        &self.session_history
+    }
+
+    /// Also track announcement start times in an `AnnouncementTracker`
+    pub fn with_announcement_tracker(mut self) -> Self {
+        self.tracker = Some(AnnouncementTracker::default());
+        self
+    }
+
+    /// Start time of the current announcement of a prefix, `None` without an announcement tracker
+    pub fn announcement_start_time(&self, prefix: &NetworkPrefix) -> Option<NaiveDateTime> {
+        let tracker = self.tracker.as_ref()?;
+        tracker.announcement_start(self.tracked_peer_ip?, normalize_prefix(*prefix))
+    }
 
     pub fn open_message(&mut self, ts: DateTime<Utc>, msg: BgpOpenMessage) {
         self.update_connection_state(ts, ConnectionState::OpenSent);
@@ -129,8 +694,21 @@ impl BgpState {
         self.options = Some(msg.opt_params);
     }
 
+    /// Records a NOTIFICATION and moves the connection to idle
+    pub fn notification_message(&mut self, ts: DateTime<Utc>, msg: &BgpNotificationMessage) -> &NotificationRecord {
+        self.update_connection_state(ts, ConnectionState::Idle);
+        self.notifications.push(NotificationRecord::from_message(ts, msg));
+        self.notifications.last().unwrap()
+    }
+
+    /// The NOTIFICATION messages received from the peer, oldest first
+    pub fn notifications(&self) -> &[NotificationRecord] {
+        &self.notifications
+    }
+
     /// Updates the connection state and timestamp
     pub fn update_connection_state(&mut self, ts: DateTime<Utc>, new_state: ConnectionState) {
+        self.observe(ts);
         match (&self.connection_state, &new_state) {
             (ConnectionState::Established, ConnectionState::Established) => {
                 log::warn!("{}: Connection state changed from Established to Established for peer.", ts);
@@ -138,39 +716,718 @@ impl BgpState {
             },
             (_, ConnectionState::Established) => {
                 log::warn!("{}: Connection state changed from {} to Established for peer.", ts, self.connection_state);
-                self.prefix_announcements.clear();
+                self.clear_announcements();
                 self.last_message_timestamp = Some(ts);
+                self.established_at = Some(ts);
+                self.post_established_prefix_rates.clear();
             },
             (_, ConnectionState::Idle) => {
-                self.prefix_announcements.clear();
+                self.clear_announcements();
                 self.last_message_timestamp = None;
+                self.established_at = None;
+                self.post_established_prefix_rates.clear();
             },
             _ => {
-                self.prefix_announcements.clear();
+                self.clear_announcements();
                 self.last_message_timestamp = None;
+                self.established_at = None;
+                self.post_established_prefix_rates.clear();
             },
         }
 
+        if self.connection_state != new_state {
+            self.session_history.push(SessionEvent { timestamp: ts, from: self.connection_state.clone(), to: new_state.clone() });
+        }
         self.connection_state = new_state;
     }
 
     pub fn update_last_message_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.observe(timestamp);
+        self.sample_prefix_count(timestamp);
         self.last_message_timestamp = self.last_message_timestamp
             .map(|ts| ts.max(timestamp))
             .or(Some(timestamp));
     }
 
+    // Record the prefix count at every sample interval that ended before `ts`, until the count is stable
+    fn sample_prefix_count(&mut self, ts: DateTime<Utc>) {
+        let Some(established_at) = self.established_at else {
+            return;
+        };
+        let elapsed = (ts - established_at).to_std().unwrap_or_default();
+        while self.post_established_prefix_rates.len() < MAX_CONVERGENCE_SAMPLES && self.estimated_convergence_time().is_none() {
+            let next = CONVERGENCE_SAMPLE_INTERVAL * (self.post_established_prefix_rates.len() as u32 + 1);
+            if next > elapsed {
+                break;
+            }
+            self.post_established_prefix_rates.push((next, self.prefix_announcements.len()));
+        }
+    }
+
+    /// Time of the last transition to Established, `None` when the session is not established
+    pub fn established_since(&self) -> Option<DateTime<Utc>> {
+        self.established_at
+    }
+
+    /// Prefix count samples since the session was established, see `CONVERGENCE_SAMPLE_INTERVAL`
+    pub fn post_established_prefix_rates(&self) -> &[(Duration, usize)] {
+        &self.post_established_prefix_rates
+    }
+
+    /// Time after the session was established until the prefix count grew by less than 1% in a sample interval.
+    ///
+    /// `None` while the peer is still converging, has no prefixes or is not established.
+    pub fn estimated_convergence_time(&self) -> Option<Duration> {
+        self.post_established_prefix_rates.windows(2).find_map(|pair| {
+            let ((converged_at, previous), (_, current)) = (pair[0], pair[1]);
+            let growth = (current as f64 - previous as f64) / previous as f64;
+            (previous > 0 && growth < CONVERGENCE_GROWTH_THRESHOLD).then_some(converged_at)
+        })
+    }
+
+    // Extend the observed time range of the peer
+    fn observe(&mut self, ts: DateTime<Utc>) {
+        self.first_seen = Some(self.first_seen.map_or(ts, |first| first.min(ts)));
+        self.last_seen = Some(self.last_seen.map_or(ts, |last| last.max(ts)));
+    }
+
+    /// Earliest timestamp observed for the peer
+    pub fn first_seen(&self) -> Option<DateTime<Utc>> {
+        self.first_seen
+    }
+
+    /// Latest timestamp observed for the peer
+    pub fn last_seen(&self) -> Option<DateTime<Utc>> {
+        self.last_seen
+    }
+
     /// Adds or updates an announcement for a prefix
     pub fn update_prefix(&mut self, elem: BgpElem) {
-        let prefix = elem.prefix;
+        let prefix = normalize_prefix(elem.prefix);
+        let peer_ip = elem.peer_ip;
         let announcement = Announcement::from_bgp_elem(elem).unwrap();
+        if let Some(tracker) = self.tracker.as_mut() {
+            let peer_ip = *self.tracked_peer_ip.get_or_insert(peer_ip);
+            tracker.add_announcement(peer_ip, prefix, announcement.timestamp.naive_utc());
+        }
 
         self.update_last_message_timestamp(announcement.timestamp);
+        self.burst_tracker.record(announcement.timestamp, prefix);
+        if let Some(old) = self.prefix_announcements.remove(&prefix) {
+            unindex_communities(&mut self.community_index, &prefix, &old);
+        }
+        index_communities(&mut self.community_index, prefix, &announcement);
+        self.prefix_announcements.insert(prefix, announcement);
+        self.peak_prefix_count = self.peak_prefix_count.max(self.prefix_announcements.len());
+    }
+
+    /// Adds an announcement only if the prefix is not announced yet, without touching timestamps or update bursts.
+    ///
+    /// Returns whether the announcement was added.
+    pub fn add_prefix_if_absent(&mut self, elem: BgpElem) -> bool {
+        let prefix = normalize_prefix(elem.prefix);
+        if self.prefix_announcements.contains_key(&prefix) {
+            return false;
+        }
+
+        let announcement = Announcement::from_bgp_elem(elem).unwrap();
+        index_communities(&mut self.community_index, prefix, &announcement);
         self.prefix_announcements.insert(prefix, announcement);
+        self.peak_prefix_count = self.peak_prefix_count.max(self.prefix_announcements.len());
+        true
+    }
+
+    /// Withdraws the announcement for a prefix.
+    ///
+    /// Returns whether the prefix was announced, withdrawals of unknown prefixes are counted as orphan withdrawals.
+    pub fn withdraw_prefix(&mut self, ts: f64, prefix: NetworkPrefix) -> bool {
+        let ts = timestamp_to_datetime(ts);
+        let prefix = normalize_prefix(prefix);
+        self.update_last_message_timestamp(ts);
+        self.burst_tracker.record(ts, prefix);
+        if let (Some(tracker), Some(peer_ip)) = (self.tracker.as_mut(), self.tracked_peer_ip) {
+            tracker.withdraw_announcement(peer_ip, prefix);
+        }
+        match self.prefix_announcements.remove(&prefix) {
+            Some(old) => {
+                unindex_communities(&mut self.community_index, &prefix, &old);
+                true
+            },
+            None => {
+                self.orphan_withdrawals += 1;
+                false
+            },
+        }
     }
 
-    pub fn withdraw_prefix(&mut self, ts: f64, prefix: NetworkPrefix) {
+    fn clear_announcements(&mut self) {
+        self.prefix_announcements.clear();
+        self.vpn_announcements.clear();
+        self.community_index.clear();
+        if let Some(tracker) = self.tracker.as_mut() {
+            *tracker = AnnouncementTracker::default();
+        }
+    }
+
+    /// Adds or updates the announcement of a VPN route
+    pub fn update_vpn_prefix(&mut self, rd: RouteDistinguisher, elem: BgpElem) {
+        let prefix = normalize_prefix(elem.prefix);
+        let announcement = Announcement::from_bgp_elem(elem).unwrap();
+        self.update_last_message_timestamp(announcement.timestamp);
+        self.vpn_announcements.insert((rd, prefix), announcement);
+    }
+
+    /// Withdraws the announcement of a VPN route, returns whether it was announced
+    pub fn withdraw_vpn_prefix(&mut self, ts: f64, rd: RouteDistinguisher, prefix: NetworkPrefix) -> bool {
         self.update_last_message_timestamp(timestamp_to_datetime(ts));
-        self.prefix_announcements.remove(&prefix);
+        self.vpn_announcements.remove(&(rd, normalize_prefix(prefix))).is_some()
+    }
+
+    /// The VPN routes currently announced by the peer
+    pub fn vpn_announcements(&self) -> &HashMap<(RouteDistinguisher, NetworkPrefix), Announcement, S> {
+        &self.vpn_announcements
+    }
+
+    /// Prefixes currently announced with the given community, in arbitrary order
+    pub fn prefixes_with_community(&self, community: &MetaCommunity) -> impl Iterator<Item = &NetworkPrefix> {
+        self.community_index.get(&CommunityKey(*community)).into_iter().flatten()
+    }
+
+    /// Rebuild the community index from the announcements, needed after deserializing the state
+    pub fn rebuild_community_index(&mut self) {
+        self.community_index.clear();
+        for (prefix, ann) in &self.prefix_announcements {
+            index_communities(&mut self.community_index, *prefix, ann);
+        }
+    }
+
+    /// Number of withdrawals for prefixes that were not announced
+    pub fn orphan_withdrawals(&self) -> u64 {
+        self.orphan_withdrawals
+    }
+
+
+    /// Number of announcements and withdrawals per prefix in the 60 seconds before `as_of`
+    pub fn burst_counts(&self, as_of: DateTime<Utc>) -> HashMap<NetworkPrefix, u32> {
+        self.burst_tracker.counts(as_of)
+    }
+
+    /// Prefixes with more than `threshold` announcements and withdrawals in the 60 seconds before `as_of`
+    pub fn oscillating_prefixes(&self, as_of: DateTime<Utc>, threshold: u32) -> Vec<(NetworkPrefix, u32)> {
+        let mut result: Vec<(NetworkPrefix, u32)> = self.burst_counts(as_of)
+            .into_iter()
+            .filter(|(_, count)| *count > threshold)
+            .collect();
+        result.sort_by_key(|(prefix, count)| (std::cmp::Reverse(*count), prefix_sort_key(prefix), prefix.path_id));
+        result
+    }
+
+    /// Forget the recorded update events, e.g. after loading a table dump
+    pub fn clear_bursts(&mut self) {
+        self.burst_tracker.clear();
+    }
+
+    /// The current state of the BGP connection
+    pub fn connection_state(&self) -> &ConnectionState {
+        &self.connection_state
+    }
+
+    /// Timestamp of the last received message
+    pub fn last_message_timestamp(&self) -> Option<DateTime<Utc>> {
+        self.last_message_timestamp
+    }
+
+    /// Whether the peer has been silent for longer than `hold_time` at `as_of`, false without any message
+    pub fn hold_timer_expired(&self, as_of: DateTime<Utc>, hold_time: chrono::Duration) -> bool {
+        self.last_message_timestamp.is_some_and(|last| as_of - last > hold_time)
+    }
+
+    /// Hold time from the last open message
+    pub fn hold_time(&self) -> Option<u16> {
+        self.hold_time
+    }
+
+    /// BGP options from the last open message
+    pub fn options(&self) -> Option<&Vec<OptParam>> {
+        self.options.as_ref()
+    }
+
+    /// The announcements currently held for this peer
+    pub fn announcements(&self) -> &HashMap<NetworkPrefix, Announcement, S> {
+        &self.prefix_announcements
+    }
+
+    /// The announcements ordered by prefix (IPv4 before IPv6, then numerically)
+    pub fn sorted_announcements(&self) -> Vec<(&NetworkPrefix, &Announcement)> {
+        let mut announcements: Vec<(&NetworkPrefix, &Announcement)> = self.prefix_announcements.iter().collect();
+        announcements.sort_by_key(|(prefix, _)| (prefix_sort_key(prefix), prefix.path_id));
+        announcements
+    }
+
+    /// Announcements added, removed and changed from `self` to `other`, each ordered by prefix.
+    ///
+    /// Re-announcements with the same route attributes are not changes, the cached `Announcement::attribute_hash`
+    /// skips the field comparison for most changed prefixes.
+    pub fn diff(&self, other: &BgpState<S>) -> BgpStateDiff {
+        let mut diff = BgpStateDiff::default();
+        for (prefix, ann) in &self.prefix_announcements {
+            match other.prefix_announcements.get(prefix) {
+                None => diff.removed.push(*prefix),
+                Some(other_ann) if !ann.same_attributes(other_ann) => diff.changed.push(*prefix),
+                Some(_) => {},
+            }
+        }
+        diff.added = other.prefix_announcements.keys().filter(|prefix| !self.prefix_announcements.contains_key(prefix)).copied().collect();
+
+        for prefixes in [&mut diff.added, &mut diff.removed, &mut diff.changed] {
+            prefixes.sort_by_key(|prefix| (prefix_sort_key(prefix), prefix.path_id));
+        }
+        diff
+    }
+
+    /// The announcements whose AS path contains `asn`, ordered by prefix
+    pub fn prefixes_transiting_as(&self, asn: u32) -> Vec<(&NetworkPrefix, &Announcement)> {
+        self.sorted_announcements()
+            .into_iter()
+            .filter(|(_, ann)| ann.as_path.as_ref().is_some_and(|path| {
+                path.iter_segments().any(|segment| segment.iter().any(|a| a.to_u32() == asn))
+            }))
+            .collect()
+    }
+
+    /// The announcements originated by `asn`, ordered by prefix
+    pub fn prefixes_originating_from_as(&self, asn: u32) -> Vec<(&NetworkPrefix, &Announcement)> {
+        self.sorted_announcements()
+            .into_iter()
+            .filter(|(_, ann)| ann.as_path.as_ref().and_then(as_path_origin) == Some(asn))
+            .collect()
+    }
+
+    /// Age of each announcement at `as_of` in seconds, announcements after `as_of` have age 0
+    pub fn announcement_ages(&self, as_of: DateTime<Utc>) -> Vec<u64> {
+        self.prefix_announcements
+            .values()
+            .map(|ann| (as_of - ann.timestamp).num_seconds().max(0) as u64)
+            .collect()
+    }
+
+    /// Distribution of the age of the current announcements at `as_of`
+    pub fn announcement_age_distribution(&self, as_of: DateTime<Utc>) -> AgeDistribution {
+        AgeDistribution::from_ages(self.announcement_ages(as_of))
+    }
+
+    /// Highest number of prefixes announced at once
+    pub fn peak_prefix_count(&self) -> usize {
+        self.peak_prefix_count
+    }
+
+    /// Currently announced prefixes relative to the peak, 1.0 when nothing was announced yet
+    pub fn reachable_prefix_ratio(&self) -> f64 {
+        match self.peak_prefix_count {
+            0 => 1.0,
+            peak => self.prefix_announcements.len() as f64 / peak as f64,
+        }
+    }
+
+    /// Number of (IPv4, IPv6) prefixes currently announced
+    pub fn prefix_counts(&self) -> (usize, usize) {
+        let v4 = self.prefix_announcements.keys().filter(|prefix| prefix.prefix.addr().is_ipv4()).count();
+        (v4, self.prefix_announcements.len() - v4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn announce(ts: f64, prefix: &str) -> BgpElem {
+        BgpElem {
+            timestamp: ts,
+            prefix: NetworkPrefix::from_str(prefix).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_oscillating_prefixes() {
+        let mut state = BgpState::new();
+        let flapping = NetworkPrefix::from_str("192.0.2.0/24").unwrap();
+
+        for i in 0..5 {
+            state.update_prefix(announce(1600000000.0 + 10.0 * i as f64, "192.0.2.0/24"));
+            state.withdraw_prefix(1600000005.0 + 10.0 * i as f64, flapping);
+        }
+        state.update_prefix(announce(1600000000.0, "198.51.100.0/24"));
+
+        let as_of = DateTime::from_timestamp(1600000050, 0).unwrap();
+        assert_eq!(state.oscillating_prefixes(as_of, 3), vec![(flapping, 10)]);
+
+        // Only the events in the last 60 seconds count
+        let later = DateTime::from_timestamp(1600000080, 0).unwrap();
+        assert_eq!(state.oscillating_prefixes(later, 3), vec![(flapping, 6)]);
+        assert!(state.oscillating_prefixes(later, 6).is_empty());
+    }
+
+    #[test]
+    fn test_same_attributes() {
+        let ann = |ts: f64, med: Option<u32>| Announcement::from_bgp_elem(BgpElem { med, ..announce(ts, "192.0.2.0/24") }).unwrap();
+
+        // The timestamp is not a route attribute
+        let a = ann(1600000000.0, Some(10));
+        assert_eq!(a.attribute_hash(), ann(1600000100.0, Some(10)).attribute_hash());
+        assert!(a.same_attributes(&ann(1600000100.0, Some(10))));
+        assert!(!a.same_attributes(&ann(1600000000.0, Some(20))));
+
+        // A hash collision falls back to comparing the attributes
+        let b = ann(1600000000.0, Some(20));
+        b.attribute_hash.set(a.attribute_hash()).unwrap();
+        assert_eq!(a.attribute_hash(), b.attribute_hash());
+        assert!(!a.same_attributes(&b));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = BgpState::new();
+        for (ts, prefix) in [(1600000000.0, "192.0.2.0/24"), (1600000000.0, "198.51.100.0/24"), (1600000000.0, "203.0.113.0/24")] {
+            old.update_prefix(announce(ts, prefix));
+        }
+        let mut new = old.clone();
+        // Same attributes at a later time, changed MED, withdrawn and new prefix
+        new.update_prefix(announce(1600000100.0, "192.0.2.0/24"));
+        new.update_prefix(BgpElem { med: Some(20), ..announce(1600000100.0, "198.51.100.0/24") });
+        new.withdraw_prefix(1600000100.0, NetworkPrefix::from_str("203.0.113.0/24").unwrap());
+        new.update_prefix(announce(1600000100.0, "2001:db8::/32"));
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, vec![NetworkPrefix::from_str("2001:db8::/32").unwrap()]);
+        assert_eq!(diff.removed, vec![NetworkPrefix::from_str("203.0.113.0/24").unwrap()]);
+        assert_eq!(diff.changed, vec![NetworkPrefix::from_str("198.51.100.0/24").unwrap()]);
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn test_unnormalized_prefixes() {
+        let mut state = BgpState::new();
+        state.update_prefix(announce(1600000000.0, "192.0.2.1/24"));
+        state.update_prefix(announce(1600000000.0, "2001:db8:1:ff::/48"));
+        assert!(state.announcements().contains_key(&NetworkPrefix::from_str("192.0.2.0/24").unwrap()));
+        assert!(state.announcements().contains_key(&NetworkPrefix::from_str("2001:db8:1::/48").unwrap()));
+
+        assert!(state.withdraw_prefix(1600000001.0, NetworkPrefix::from_str("192.0.2.255/24").unwrap()));
+        assert!(state.withdraw_prefix(1600000001.0, NetworkPrefix::from_str("2001:db8:1::/48").unwrap()));
+        assert!(state.announcements().is_empty());
+    }
+
+    #[test]
+    fn test_community_index() {
+        use bgpkit_parser::models::Community;
+
+        let blackhole = MetaCommunity::Plain(Community::Custom(65535.into(), 666));
+        let no_export = MetaCommunity::Plain(Community::NoExport);
+        let with_communities = |ts: f64, prefix: &str, communities: Vec<MetaCommunity>| BgpElem { communities: Some(communities), ..announce(ts, prefix) };
+        let prefixes = |state: &BgpState, community: &MetaCommunity| {
+            let mut prefixes: Vec<String> = state.prefixes_with_community(community).map(|p| p.to_string()).collect();
+            prefixes.sort();
+            prefixes
+        };
+
+        let mut state = BgpState::new();
+        state.update_prefix(with_communities(1600000000.0, "192.0.2.0/24", vec![blackhole, no_export]));
+        state.update_prefix(with_communities(1600000000.0, "198.51.100.0/24", vec![no_export]));
+        assert_eq!(prefixes(&state, &blackhole), vec!["192.0.2.0/24"]);
+        assert_eq!(prefixes(&state, &no_export), vec!["192.0.2.0/24", "198.51.100.0/24"]);
+
+        // Re-announcement without the blackhole community
+        state.update_prefix(with_communities(1600000001.0, "192.0.2.0/24", vec![no_export]));
+        assert!(prefixes(&state, &blackhole).is_empty());
+        assert_eq!(prefixes(&state, &no_export), vec!["192.0.2.0/24", "198.51.100.0/24"]);
+
+        state.withdraw_prefix(1600000002.0, NetworkPrefix::from_str("198.51.100.0/24").unwrap());
+        assert_eq!(prefixes(&state, &no_export), vec!["192.0.2.0/24"]);
+
+        // The index is not serialized but can be rebuilt
+        let mut restored: BgpState = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert!(prefixes(&restored, &no_export).is_empty());
+        restored.rebuild_community_index();
+        assert_eq!(prefixes(&restored, &no_export), vec!["192.0.2.0/24"]);
+
+        state.update_connection_state(DateTime::from_timestamp(1600000003, 0).unwrap(), ConnectionState::Idle);
+        assert!(prefixes(&state, &no_export).is_empty());
+    }
+
+    #[test]
+    fn test_prefixes_by_as() {
+        let with_path = |prefix: &str, path: &[u32]| BgpElem { as_path: Some(AsPath::from_sequence(path)), ..announce(1600000000.0, prefix) };
+        let prefixes = |anns: Vec<(&NetworkPrefix, &Announcement)>| anns.into_iter().map(|(p, _)| p.to_string()).collect::<Vec<_>>();
+
+        let mut state = BgpState::new();
+        state.update_prefix(with_path("198.51.100.0/24", &[65001, 3356, 65010]));
+        state.update_prefix(with_path("192.0.2.0/24", &[65001, 3356]));
+        state.update_prefix(with_path("203.0.113.0/24", &[65001, 174, 65020]));
+        state.update_prefix(announce(1600000000.0, "2001:db8::/32"));
+
+        assert_eq!(prefixes(state.prefixes_transiting_as(3356)), vec!["192.0.2.0/24", "198.51.100.0/24"]);
+        assert_eq!(prefixes(state.prefixes_transiting_as(65001)).len(), 3);
+        assert_eq!(prefixes(state.prefixes_originating_from_as(3356)), vec!["192.0.2.0/24"]);
+        assert_eq!(prefixes(state.prefixes_originating_from_as(65020)), vec!["203.0.113.0/24"]);
+        assert!(state.prefixes_originating_from_as(65001).is_empty());
+    }
+
+    #[test]
+    fn test_reachable_prefix_ratio() {
+        let mut state = BgpState::new();
+        assert_eq!(state.reachable_prefix_ratio(), 1.0);
+
+        for prefix in ["192.0.2.0/24", "198.51.100.0/24", "203.0.113.0/24", "2001:db8::/32"] {
+            state.update_prefix(announce(1600000000.0, prefix));
+        }
+        state.withdraw_prefix(1600000001.0, NetworkPrefix::from_str("192.0.2.0/24").unwrap());
+        state.withdraw_prefix(1600000001.0, NetworkPrefix::from_str("198.51.100.0/24").unwrap());
+        state.withdraw_prefix(1600000001.0, NetworkPrefix::from_str("203.0.113.0/24").unwrap());
+        assert_eq!(state.peak_prefix_count(), 4);
+        assert_eq!(state.reachable_prefix_ratio(), 0.25);
+
+        // The peak survives a session reset
+        state.update_connection_state(DateTime::from_timestamp(1600000002, 0).unwrap(), ConnectionState::Idle);
+        assert_eq!(state.reachable_prefix_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_estimated_convergence_time() {
+        let established = DateTime::from_timestamp(1600000000, 0).unwrap();
+        let mut state = BgpState::new();
+        state.update_connection_state(established, ConnectionState::Established);
+
+        // 100 prefixes in the first 30 seconds, 50 in the next, one more in the third interval
+        for i in 0..150 {
+            let ts = if i < 100 { 1600000000.0 + i as f64 * 0.1 } else { 1600000035.0 };
+            state.update_prefix(announce(ts, &format!("10.{}.{}.0/24", i / 256, i % 256)));
+        }
+        state.update_prefix(announce(1600000070.0, "192.0.2.0/24"));
+        assert_eq!(state.estimated_convergence_time(), None);
+
+        // Quiet since then, the next message closes the third interval
+        state.update_last_message_timestamp(DateTime::from_timestamp(1600000125, 0).unwrap());
+        assert_eq!(state.post_established_prefix_rates()[..3], [(Duration::from_secs(30), 100), (Duration::from_secs(60), 150), (Duration::from_secs(90), 151)]);
+        assert_eq!(state.estimated_convergence_time(), Some(Duration::from_secs(60)));
+
+        // Sampling stops once the count is stable
+        state.update_last_message_timestamp(DateTime::from_timestamp(1600003600, 0).unwrap());
+        assert_eq!(state.post_established_prefix_rates().len(), 3);
+
+        state.update_connection_state(DateTime::from_timestamp(1600003601, 0).unwrap(), ConnectionState::Idle);
+        assert_eq!(state.estimated_convergence_time(), None);
+    }
+
+    #[test]
+    fn test_vpn_announcements() {
+        let rd = RouteDistinguisher([0, 0, 0xfd, 0xe9, 0, 0, 0, 100]);
+        let other_rd = RouteDistinguisher([0, 1, 192, 0, 2, 1, 0, 7]);
+        assert_eq!(rd.to_string(), "65001:100");
+        assert_eq!(other_rd.to_string(), "192.0.2.1:7");
+
+        let mut state = BgpState::new();
+        state.update_vpn_prefix(rd, announce(1600000000.0, "10.0.0.0/8"));
+        state.update_vpn_prefix(other_rd, announce(1600000000.0, "10.0.0.0/8"));
+        assert!(state.announcements().is_empty());
+        assert_eq!(state.vpn_announcements().len(), 2);
+
+        let prefix = NetworkPrefix::from_str("10.0.0.0/8").unwrap();
+        assert!(state.withdraw_vpn_prefix(1600000001.0, rd, prefix));
+        assert!(!state.withdraw_vpn_prefix(1600000001.0, rd, prefix));
+        assert_eq!(state.vpn_announcements().keys().collect::<Vec<_>>(), vec![&(other_rd, prefix)]);
+
+        let restored: BgpState = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(restored.vpn_announcements().len(), 1);
+    }
+
+    #[test]
+    fn test_first_and_last_seen() {
+        let ts = |secs: i64| DateTime::from_timestamp(secs, 0).unwrap();
+        let mut state = BgpState::new();
+        assert_eq!((state.first_seen(), state.last_seen()), (None, None));
+
+        state.update_prefix(announce(1600000100.0, "192.0.2.0/24"));
+        state.update_connection_state(ts(1600000200), ConnectionState::Idle);
+        // Older records, e.g. from a bview, extend the range backwards
+        state.update_last_message_timestamp(ts(1600000000));
+        assert_eq!(state.first_seen(), Some(ts(1600000000)));
+        assert_eq!(state.last_seen(), Some(ts(1600000200)));
+        assert_eq!(state.last_message_timestamp(), Some(ts(1600000000)));
+    }
+
+    #[test]
+    fn test_announcement_tracker() {
+        let prefix = NetworkPrefix::from_str("192.0.2.0/24").unwrap();
+        let announce_from_peer = |ts: f64| BgpElem { peer_ip: "192.0.2.1".parse().unwrap(), ..announce(ts, "192.0.2.0/24") };
+
+        let mut untracked = BgpState::new();
+        untracked.update_prefix(announce_from_peer(1600000000.0));
+        assert_eq!(untracked.announcement_start_time(&prefix), None);
+
+        let mut state = BgpState::new().with_announcement_tracker();
+        state.update_prefix(announce_from_peer(1600000000.0));
+        assert_eq!(state.announcement_start_time(&prefix), Some(DateTime::from_timestamp(1600000000, 0).unwrap().naive_utc()));
+
+        state.withdraw_prefix(1600000010.0, prefix);
+        assert_eq!(state.announcement_start_time(&prefix), None);
+    }
+
+    #[test]
+    fn test_connection_state_u8() {
+        use bgpkit_parser::models::BgpState as MrtState;
+
+        for (mrt_state, state, v) in [
+            (MrtState::Idle, ConnectionState::Idle, 0),
+            (MrtState::Connect, ConnectionState::Connect, 1),
+            (MrtState::Active, ConnectionState::Active, 2),
+            (MrtState::OpenSent, ConnectionState::OpenSent, 3),
+            (MrtState::OpenConfirm, ConnectionState::OpenConfirm, 4),
+            (MrtState::Established, ConnectionState::Established, 5),
+        ] {
+            assert_eq!(state.to_u8(), v);
+            assert_eq!(ConnectionState::from_u8(v), Some(state.clone()));
+            assert_eq!(mrt_state.to_connection_state(), state);
+            assert_eq!(serde_json::to_string(&state).unwrap(), format!("\"{}\"", state));
+        }
+        assert_eq!(ConnectionState::from_u8(6), None);
+
+        let err = serde_json::from_str::<ConnectionState>("\"Establishd\"").unwrap_err();
+        assert!(err.to_string().starts_with("unknown variant `Establishd`"), "{}", err);
+    }
+
+    #[test]
+    fn test_session_history() {
+        let ts = |secs: i64| DateTime::from_timestamp(1600000000 + secs, 0).unwrap();
+        let mut state = BgpState::new().with_max_session_history(4);
+        for i in 0..3 {
+            state.update_connection_state(ts(i * 100), ConnectionState::Established);
+            state.update_connection_state(ts(i * 100 + 50), ConnectionState::Idle);
+        }
+        // Repeated states are not recorded
+        state.update_connection_state(ts(300), ConnectionState::Idle);
+
+        let history = state.session_history();
+        assert_eq!(history.len(), 4);
+        let timestamps: Vec<DateTime<Utc>> = history.iter().map(|event| event.timestamp).collect();
+        assert_eq!(timestamps, vec![ts(100), ts(150), ts(200), ts(250)]);
+
+        assert_eq!(history.flap_count_in_window(chrono::Duration::seconds(300), ts(300)), 2);
+        assert_eq!(history.flap_count_in_window(chrono::Duration::seconds(100), ts(300)), 1);
+        assert_eq!(history.flap_count_in_window(chrono::Duration::seconds(100), ts(200)), 1);
+        assert_eq!(history.flap_count_in_window(chrono::Duration::seconds(10), ts(300)), 0);
+
+        assert_eq!(history.flap_count(), 2);
+        assert_eq!(history.last_flap(), Some(ts(250)));
+        // Down from 150 to 200 and since 250
+        assert_eq!(history.total_downtime(ts(300)), chrono::Duration::seconds(100));
+        assert_eq!(history.total_downtime(ts(175)), chrono::Duration::seconds(25));
+    }
+
+    #[test]
+    fn test_update_connection_state_clears_announcements() {
+        let ts = |secs: i64| DateTime::from_timestamp(1600000000 + secs, 0).unwrap();
+        let mut state = BgpState::new();
+        state.update_connection_state(ts(0), ConnectionState::Established);
+        state.update_prefix(announce(1600000010.0, "192.0.2.0/24"));
+        state.update_prefix(announce(1600000020.0, "2001:db8::/32"));
+
+        // A repeated Established only logs a warning, the announcements are kept
+        state.update_connection_state(ts(30), ConnectionState::Established);
+        assert_eq!(state.announcements().len(), 2);
+        assert_eq!(state.established_since(), Some(ts(0)));
+        assert_eq!(state.session_history().len(), 1);
+
+        // Every other transition starts from an empty table
+        state.update_connection_state(ts(40), ConnectionState::Active);
+        assert!(state.announcements().is_empty());
+        state.update_connection_state(ts(50), ConnectionState::Established);
+        state.update_prefix(announce(1600000060.0, "192.0.2.0/24"));
+        state.update_connection_state(ts(70), ConnectionState::Idle);
+        assert!(state.announcements().is_empty());
+    }
+
+    #[test]
+    fn test_add_prefix_if_absent() {
+        let mut state = BgpState::new();
+        let prefix = NetworkPrefix::from_str("192.0.2.0/24").unwrap();
+        state.update_prefix(announce(1600000000.0, "192.0.2.0/24"));
+
+        assert!(!state.add_prefix_if_absent(announce(1500000000.0, "192.0.2.0/24")));
+        assert_eq!(state.announcements()[&prefix].timestamp, DateTime::from_timestamp(1600000000, 0).unwrap());
+
+        assert!(state.add_prefix_if_absent(announce(1500000000.0, "198.51.100.0/24")));
+        assert_eq!(state.announcements().len(), 2);
+        assert_eq!(state.last_message_timestamp, DateTime::from_timestamp(1600000000, 0));
+    }
+
+    #[test]
+    fn test_parse_shutdown_communication() {
+        let mut data = vec![12];
+        data.extend_from_slice(b"maintenance!trailing");
+        assert_eq!(parse_shutdown_communication(&data), Some("maintenance!".to_string()));
+
+        assert_eq!(parse_shutdown_communication(&[]), None);
+        assert_eq!(parse_shutdown_communication(&[0]), None);
+
+        // Length exceeding the data is clamped to the data
+        assert_eq!(parse_shutdown_communication(&[10, b'o', b'k']), Some("ok".to_string()));
+
+        // Messages are truncated at 128 bytes
+        let mut long = vec![255];
+        long.extend(std::iter::repeat_n(b'a', 255));
+        assert_eq!(parse_shutdown_communication(&long).unwrap().len(), MAX_SHUTDOWN_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn test_notification_record() {
+        let ts = DateTime::from_timestamp(1600000000, 0).unwrap();
+        let shutdown = BgpNotificationMessage { error: bgpkit_parser::models::BgpError::new(6, 2), data: vec![3, b'b', b'y', b'e'] };
+        let hold_timer = BgpNotificationMessage { error: bgpkit_parser::models::BgpError::new(4, 0), data: vec![3, b'b', b'y', b'e'] };
+
+        let mut state = BgpState::new();
+        assert_eq!(state.notification_message(ts, &shutdown).shutdown_message, Some("bye".to_string()));
+        assert_eq!(state.notification_message(ts, &hold_timer).shutdown_message, None);
+        assert_eq!(state.notifications().len(), 2);
+        assert_eq!(state.connection_state, ConnectionState::Idle);
+    }
+
+    #[test]
+    fn test_announcement_age_distribution() {
+        let as_of = DateTime::from_timestamp(1600000100, 0).unwrap();
+        let mut state = BgpState::new();
+        assert_eq!(state.announcement_age_distribution(as_of), AgeDistribution::default());
+
+        for i in 0..100 {
+            state.update_prefix(announce(1600000000.0 + i as f64, &format!("10.0.{i}.0/24")));
+        }
+
+        let distribution = state.announcement_age_distribution(as_of);
+        assert_eq!(distribution.total_prefixes, 100);
+        assert_eq!(distribution.min_seconds, 1);
+        assert_eq!(distribution.max_seconds, 100);
+        assert_eq!(distribution.mean_seconds, 50.5);
+        assert_eq!(distribution.p50_seconds, 50);
+        assert_eq!(distribution.p95_seconds, 95);
+        assert_eq!(distribution.p99_seconds, 99);
+    }
+
+    #[test]
+    fn test_announcement_as4_attributes() {
+        let elem = BgpElem {
+            as_path: Some(AsPath::from_sequence([65001, AS_TRANS, AS_TRANS])),
+            aggr_asn: Some(AS_TRANS.into()),
+            aggr_ip: Some("192.0.2.1".parse().unwrap()),
+            unknown: Some(vec![
+                bgpkit_parser::models::AttrRaw { attr_type: AttrType::AS4_PATH, bytes: vec![2, 2, 0, 3, 0x0d, 0x40, 0, 3, 0x0d, 0x41] },
+                bgpkit_parser::models::AttrRaw { attr_type: AttrType::AS4_AGGREGATOR, bytes: vec![0, 3, 0x0d, 0x41, 192, 0, 2, 2] },
+            ]),
+            ..announce(1600000000.0, "192.0.2.0/24")
+        };
+
+        let ann = Announcement::from_bgp_elem(elem).unwrap();
+        assert_eq!(ann.as_path, Some(AsPath::from_sequence([65001, 200000, 200001])));
+        assert_eq!(ann.aggregator, Some((200001, IpAddr::from_str("192.0.2.2").unwrap())));
     }
 }
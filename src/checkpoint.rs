@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use bgpkit_parser::models::NetworkPrefix;
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::bgp_state::BgpState;
+use crate::error::ProcessorError;
+use crate::mrt_processor::{BgpPeer, BviewInconsistency, ProcessingStats};
+
+/// Version of the checkpoint format, bumped on incompatible changes
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+/// Snapshot of the processor state.
+///
+/// Stored as gzip compressed MessagePack with structs as maps, so that fields added later can be defaulted. The
+/// serializer runs in human-readable mode, the untagged serde representations of the bgpkit models (e.g.
+/// `NetworkPrefix`) only deserialize from that, connection states are written as their `ConnectionState::to_u8` code.
+/// The counters are missing from checkpoints written before they were added and start at zero when those are loaded.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(serialize = "", deserialize = "S: BuildHasher + Default"))]
+pub struct Checkpoint<S = RandomState> {
+    pub version: u32,
+    /// Timestamp of the last record processed before the checkpoint was taken
+    pub last_processed_timestamp: Option<DateTime<Utc>>,
+    pub peers: Vec<(BgpPeer, BgpState<S>)>,
+    /// Announcements plus withdrawals per prefix, see `MrtProcessor::top_n_prefixes_by_churn`
+    #[serde(default)]
+    pub churn_counters: Vec<(NetworkPrefix, u64)>,
+    #[serde(default)]
+    pub bview_inconsistencies: Vec<BviewInconsistency>,
+    #[serde(default)]
+    pub processing_stats: ProcessingStats,
+}
+
+impl<S: BuildHasher + Default> Checkpoint<S> {
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), ProcessorError> {
+        let file = File::create(path.as_ref())?;
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+        self.serialize(&mut Serializer::new(&mut encoder).with_struct_map().with_human_readable())
+            .map_err(|e| ProcessorError::CheckpointError(format!("Failed to write {}: {}", path.as_ref().display(), e)))?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ProcessorError> {
+        let file = File::open(path.as_ref())?;
+        let mut deserializer = Deserializer::new(GzDecoder::new(BufReader::new(file))).with_human_readable();
+        let mut checkpoint: Checkpoint<S> = Checkpoint::deserialize(&mut deserializer)
+            .map_err(|e| ProcessorError::CheckpointError(format!("Failed to read {}: {}", path.as_ref().display(), e)))?;
+
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(ProcessorError::CheckpointError(format!(
+                "Unsupported checkpoint version {} in {}, expected {}",
+                checkpoint.version, path.as_ref().display(), CHECKPOINT_VERSION
+            )));
+        }
+        for (_, state) in checkpoint.peers.iter_mut() {
+            state.rebuild_community_index();
+        }
+        Ok(checkpoint)
+    }
+}
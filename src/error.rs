@@ -0,0 +1,91 @@
+use core::fmt;
+
+use bgpkit_parser::{ParserError, ParserErrorWithBytes};
+
+/// Errors while processing MRT files
+#[derive(Debug)]
+pub enum ProcessorError {
+    /// The MRT file could not be parsed
+    ParseError(ParserError),
+    IoError(std::io::Error),
+    /// The file does not contain the expected kind of MRT records
+    UnsupportedFileType { path: String, reason: String },
+    /// Adding peers would exceed the limit set with `MrtProcessor::with_max_peers`
+    PeerLimitExceeded,
+    /// A checkpoint could not be written or read
+    CheckpointError(String),
+    /// Externally provided state could not be loaded
+    InvalidState(String),
+    /// The configuration is missing or invalid
+    ConfigError(String),
+    /// The state could not be written in the requested output format
+    ExportError(String),
+    /// The state could not be serialized as JSON
+    JsonError(serde_json::Error),
+    /// The state could not be serialized as YAML
+    YamlError(serde_yaml::Error),
+}
+
+impl fmt::Display for ProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessorError::ParseError(e) => write!(f, "Parse error: {}", e),
+            ProcessorError::IoError(e) => write!(f, "IO error: {}", e),
+            ProcessorError::UnsupportedFileType { path, reason } => write!(f, "Unsupported file type for {}: {}", path, reason),
+            ProcessorError::CheckpointError(reason) => write!(f, "Checkpoint error: {}", reason),
+            ProcessorError::PeerLimitExceeded => write!(f, "Peer limit exceeded"),
+            ProcessorError::InvalidState(reason) => write!(f, "Invalid state: {}", reason),
+            ProcessorError::ConfigError(reason) => write!(f, "Configuration error: {}", reason),
+            ProcessorError::ExportError(reason) => write!(f, "Export error: {}", reason),
+            ProcessorError::JsonError(e) => write!(f, "JSON error: {}", e),
+            ProcessorError::YamlError(e) => write!(f, "YAML error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProcessorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProcessorError::ParseError(e) => Some(e),
+            ProcessorError::IoError(e) => Some(e),
+            ProcessorError::JsonError(e) => Some(e),
+            ProcessorError::YamlError(e) => Some(e),
+            ProcessorError::UnsupportedFileType { .. }
+            | ProcessorError::PeerLimitExceeded
+            | ProcessorError::CheckpointError(_)
+            | ProcessorError::InvalidState(_)
+            | ProcessorError::ConfigError(_)
+            | ProcessorError::ExportError(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ProcessorError {
+    fn from(e: std::io::Error) -> Self {
+        ProcessorError::IoError(e)
+    }
+}
+
+impl From<serde_json::Error> for ProcessorError {
+    fn from(e: serde_json::Error) -> Self {
+        ProcessorError::JsonError(e)
+    }
+}
+
+impl From<serde_yaml::Error> for ProcessorError {
+    fn from(e: serde_yaml::Error) -> Self {
+        ProcessorError::YamlError(e)
+    }
+}
+
+impl From<ParserError> for ProcessorError {
+    fn from(e: ParserError) -> Self {
+        ProcessorError::ParseError(e)
+    }
+}
+
+impl From<ParserErrorWithBytes> for ProcessorError {
+    fn from(e: ParserErrorWithBytes) -> Self {
+        ProcessorError::ParseError(e.error)
+    }
+}
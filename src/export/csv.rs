@@ -0,0 +1,181 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+
+use crate::bgp_state::BgpState;
+use crate::error::ProcessorError;
+use crate::mrt_processor::{sorted_peers, BgpPeer, PeerFlapReport};
+use crate::mrt_records::format_community;
+use crate::util::format_prefix;
+
+const HEADER: &str = "peer_ip,peer_asn,connection_state,first_seen,last_seen,prefix,timestamp,as_path,origin,next_hop,local_pref,med,communities";
+
+const FLAP_REPORT_HEADER: &str = "peer_ip,peer_asn,flap_count,total_downtime_seconds,last_flap,current_state,uptime_seconds";
+
+fn opt_to_string<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+// Quote a field containing a separator, quote or line break (RFC 4180 §2), e.g. an AS path with an AS set
+fn quote_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Write the state as CSV with one row per announcement, ordered by peer and prefix.
+///
+/// AS paths and communities are space separated within their column, fields containing a comma (e.g. the AS sets of
+/// a path) are quoted.
+pub fn export_csv<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, w: &mut W) -> Result<(), ProcessorError> {
+    writeln!(w, "{HEADER}")?;
+
+    for (peer, peer_state) in sorted_peers(state) {
+        for (prefix, ann) in peer_state.sorted_announcements() {
+            let communities = ann.communities.as_ref()
+                .map(|communities| communities.iter().map(format_community).collect::<Vec<String>>().join(" "))
+                .unwrap_or_default();
+
+            writeln!(
+                w,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                peer.address,
+                peer.peer_as,
+                peer_state.connection_state(),
+                opt_to_string(peer_state.first_seen().map(|ts| ts.to_rfc3339())),
+                opt_to_string(peer_state.last_seen().map(|ts| ts.to_rfc3339())),
+                format_prefix(prefix),
+                ann.timestamp.to_rfc3339(),
+                quote_field(&opt_to_string(ann.as_path.as_ref())),
+                opt_to_string(ann.origin),
+                opt_to_string(ann.next_hop),
+                opt_to_string(ann.local_pref),
+                opt_to_string(ann.med),
+                quote_field(&communities),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a peer flap report as CSV with one row per peer, in the order of the report.
+///
+/// Durations are in seconds, the uptime is empty for peers that are not established.
+pub fn export_flap_report_csv<W: Write>(report: &[PeerFlapReport], w: &mut W) -> Result<(), ProcessorError> {
+    writeln!(w, "{FLAP_REPORT_HEADER}")?;
+
+    for row in report {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{}",
+            row.peer.address,
+            row.peer.peer_as,
+            row.flap_count,
+            row.total_downtime.num_seconds(),
+            opt_to_string(row.last_flap_ts.map(|ts| ts.to_rfc3339())),
+            row.current_state,
+            opt_to_string(row.uptime.map(|uptime| uptime.num_seconds())),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgpkit_parser::models::{AsPath, AsPathSegment, BgpElem, NetworkPrefix};
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_export_csv() {
+        let peer = BgpPeer { address: IpAddr::from_str("192.0.2.1").unwrap(), peer_as: 65001 };
+        let mut peer_state = BgpState::new();
+        peer_state.update_prefix(BgpElem {
+            timestamp: 1600000000.0,
+            prefix: NetworkPrefix::from_str("198.51.100.0/24").unwrap(),
+            next_hop: Some(IpAddr::from_str("192.0.2.1").unwrap()),
+            as_path: Some(AsPath::from_sequence([65001, 65002])),
+            ..Default::default()
+        });
+
+        let mut state = HashMap::new();
+        state.insert(peer, peer_state);
+
+        let mut out = Vec::new();
+        export_csv(&state, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[0], HEADER);
+        assert_eq!(lines[1], "192.0.2.1,65001,Idle,2020-09-13T12:26:40+00:00,2020-09-13T12:26:40+00:00,198.51.100.0/24,2020-09-13T12:26:40+00:00,65001 65002,,192.0.2.1,,,");
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn test_export_csv_as_set() {
+        let peer = BgpPeer { address: IpAddr::from_str("192.0.2.1").unwrap(), peer_as: 65001 };
+        let mut peer_state = BgpState::new();
+        peer_state.update_prefix(BgpElem {
+            timestamp: 1600000000.0,
+            prefix: NetworkPrefix::from_str("198.51.100.0/24").unwrap(),
+            next_hop: Some(IpAddr::from_str("192.0.2.1").unwrap()),
+            as_path: Some(AsPath::from_segments(vec![AsPathSegment::sequence([65001, 65002]), AsPathSegment::set([65010, 65011])])),
+            med: Some(10),
+            ..Default::default()
+        });
+
+        let mut out = Vec::new();
+        export_csv(&HashMap::from([(peer, peer_state)]), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let row = out.lines().nth(1).unwrap();
+        assert!(row.ends_with(",\"65001 65002 {65010,65011}\",,192.0.2.1,,10,"), "{}", row);
+        assert_eq!(quote_field("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn test_export_csv_ipv6_next_hop() {
+        // IPv4 prefix with an IPv6 next hop (RFC 5549)
+        let peer = BgpPeer { address: IpAddr::from_str("2001:db8::1").unwrap(), peer_as: 65001 };
+        let mut peer_state = BgpState::new();
+        peer_state.update_prefix(BgpElem {
+            timestamp: 1600000000.0,
+            prefix: NetworkPrefix::from_str("198.51.100.0/24").unwrap(),
+            next_hop: Some(IpAddr::from_str("2001:db8::1").unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(peer_state.announcements()[&NetworkPrefix::from_str("198.51.100.0/24").unwrap()].next_hop, Some(IpAddr::from_str("2001:db8::1").unwrap()));
+
+        let mut out = Vec::new();
+        export_csv(&HashMap::from([(peer, peer_state)]), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().nth(1).unwrap().contains(",198.51.100.0/24,2020-09-13T12:26:40+00:00,,,2001:db8::1,"));
+    }
+
+    #[test]
+    fn test_export_flap_report_csv() {
+        use crate::bgp_state::ConnectionState;
+        use chrono::DateTime;
+
+        let report = vec![PeerFlapReport {
+            peer: BgpPeer { address: IpAddr::from_str("192.0.2.1").unwrap(), peer_as: 65001 },
+            flap_count: 2,
+            total_downtime: chrono::Duration::seconds(180),
+            last_flap_ts: DateTime::from_timestamp(1600000000, 0),
+            current_state: ConnectionState::Active,
+            uptime: None,
+        }];
+
+        let mut out = Vec::new();
+        export_flap_report_csv(&report, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{FLAP_REPORT_HEADER}\n192.0.2.1,65001,2,180,2020-09-13T12:26:40+00:00,Active,\n")
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+
+use crate::bgp_state::BgpState;
+use crate::error::ProcessorError;
+use crate::mrt_processor::{sorted_peers, BgpPeer};
+
+/// Write one InfluxDB line protocol point per peer.
+///
+/// The point carries the timestamp of the last message from the peer, peers without messages are written without
+/// a timestamp.
+pub fn export_influx<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, w: &mut W) -> Result<(), ProcessorError> {
+    for (peer, peer_state) in sorted_peers(state) {
+        let (v4, v6) = peer_state.prefix_counts();
+        write!(
+            w,
+            "bgp_peer,peer_ip={},peer_asn={} state=\"{}\",prefixes_v4={}i,prefixes_v6={}i",
+            peer.address, peer.peer_as, peer_state.connection_state(), v4, v6
+        )?;
+        match peer_state.last_message_timestamp().and_then(|ts| ts.timestamp_nanos_opt()) {
+            Some(ns) => writeln!(w, " {ns}")?,
+            None => writeln!(w)?,
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+
+use crate::bgp_state::BgpState;
+use crate::error::ProcessorError;
+use crate::export::peer_records;
+use crate::mrt_processor::BgpPeer;
+
+/// Write the state as a JSON array with one object per peer, ordered by peer and prefix
+pub fn export_json<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, w: &mut W) -> Result<(), ProcessorError> {
+    serde_json::to_writer_pretty(&mut *w, &peer_records(state))?;
+    writeln!(w)?;
+    Ok(())
+}
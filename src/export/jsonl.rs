@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::net::IpAddr;
+
+use bgpkit_parser::models::NetworkPrefix;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::bgp_state::BgpState;
+use crate::error::ProcessorError;
+use crate::mrt_processor::{sorted_peers, BgpPeer};
+use crate::mrt_records::format_community;
+
+/// A single line of the JSONL output
+#[derive(Serialize)]
+struct AnnouncementLine<'a> {
+    peer_ip: IpAddr,
+    peer_asn: u32,
+    prefix: &'a NetworkPrefix,
+    next_hop: Option<IpAddr>,
+    as_path: Option<String>,
+    communities: Vec<String>,
+    announced_at: DateTime<Utc>,
+}
+
+/// Write the state as JSON Lines with one object per announcement, ordered by peer and prefix.
+///
+/// The writer is flushed after every line, so the output can be streamed into another process.
+pub fn export_jsonl<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, w: &mut W) -> Result<(), ProcessorError> {
+    for (peer, peer_state) in sorted_peers(state) {
+        for (prefix, ann) in peer_state.sorted_announcements() {
+            let line = AnnouncementLine {
+                peer_ip: peer.address,
+                peer_asn: peer.peer_as,
+                prefix,
+                next_hop: ann.next_hop,
+                as_path: ann.as_path.as_ref().map(|path| path.to_string()),
+                communities: ann.communities.iter().flatten().map(format_community).collect(),
+                announced_at: ann.timestamp,
+            };
+            serde_json::to_writer(&mut *w, &line)?;
+            writeln!(w)?;
+            w.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgpkit_parser::models::{AsPath, BgpElem};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_export_jsonl() {
+        let peer = BgpPeer { address: IpAddr::from_str("192.0.2.1").unwrap(), peer_as: 65001 };
+        let mut peer_state = BgpState::new();
+        for prefix in ["203.0.113.0/24", "198.51.100.0/24"] {
+            peer_state.update_prefix(BgpElem {
+                timestamp: 1600000000.0,
+                prefix: NetworkPrefix::from_str(prefix).unwrap(),
+                next_hop: Some(IpAddr::from_str("192.0.2.1").unwrap()),
+                as_path: Some(AsPath::from_sequence([65001, 65002])),
+                ..Default::default()
+            });
+        }
+
+        let mut state = HashMap::new();
+        state.insert(peer, peer_state);
+
+        let mut out = Vec::new();
+        export_jsonl(&state, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            r#"{"peer_ip":"192.0.2.1","peer_asn":65001,"prefix":"198.51.100.0/24","next_hop":"192.0.2.1","as_path":"65001 65002","communities":[],"announced_at":"2020-09-13T12:26:40Z"}"#
+        );
+        assert!(lines.iter().all(|line| serde_json::from_str::<serde_json::Value>(line).is_ok()));
+    }
+}
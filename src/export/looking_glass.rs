@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::net::IpAddr;
+
+use bgpkit_parser::models::NetworkPrefix;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Serialize, Serializer};
+
+use crate::bgp_state::{Announcement, BgpState, ConnectionState};
+use crate::error::ProcessorError;
+use crate::export::AnnouncementRecord;
+use crate::mrt_processor::{sorted_peers, BgpPeer};
+
+/// A looking glass query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookingGlassQuery {
+    /// Routes for exactly this prefix
+    PrefixExact(NetworkPrefix),
+    /// The most specific route covering the address, per peer
+    PrefixLongestMatch(IpAddr),
+    /// Summary of all peers
+    AllPeers,
+    /// All routes of a single peer
+    PeerDetail(BgpPeer),
+}
+
+impl LookingGlassQuery {
+    fn query_type(&self) -> &'static str {
+        match self {
+            LookingGlassQuery::PrefixExact(_) => "prefix_exact",
+            LookingGlassQuery::PrefixLongestMatch(_) => "prefix_longest_match",
+            LookingGlassQuery::AllPeers => "all_peers",
+            LookingGlassQuery::PeerDetail(_) => "peer_detail",
+        }
+    }
+}
+
+/// Answer to a looking glass query
+#[derive(Debug, Clone, Serialize)]
+pub struct LookingGlassResponse {
+    pub query_type: &'static str,
+    pub results: Vec<LookingGlassResult>,
+    /// Number of peers in the state
+    pub total_peers: usize,
+    /// Number of announcements over all peers
+    pub total_prefixes: usize,
+}
+
+/// A matching route or peer
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum LookingGlassResult {
+    Route {
+        peer_ip: IpAddr,
+        peer_asn: u32,
+        #[serde(flatten)]
+        route: RouteEntry,
+    },
+    Peer {
+        peer_ip: IpAddr,
+        peer_asn: u32,
+        connection_state: ConnectionState,
+        prefixes_v4: usize,
+        prefixes_v6: usize,
+        last_message_timestamp: Option<DateTime<Utc>>,
+    },
+}
+
+/// Prefix and announcement of a route, serialized like the announcements of the JSON export
+#[derive(Debug, Clone)]
+pub struct RouteEntry {
+    pub prefix: NetworkPrefix,
+    pub announcement: Announcement,
+}
+
+impl Serialize for RouteEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AnnouncementRecord::new(&self.prefix, &self.announcement).serialize(serializer)
+    }
+}
+
+fn route(peer: &BgpPeer, (prefix, announcement): (&NetworkPrefix, &Announcement)) -> LookingGlassResult {
+    LookingGlassResult::Route {
+        peer_ip: peer.address,
+        peer_asn: peer.peer_as,
+        route: RouteEntry { prefix: *prefix, announcement: announcement.clone() },
+    }
+}
+
+/// Most specific announced prefix covering `addr`, probing each prefix length from the longest
+fn longest_match<S: BuildHasher + Default>(peer_state: &BgpState<S>, addr: IpAddr) -> Option<(&NetworkPrefix, &Announcement)> {
+    let max_len = if addr.is_ipv4() { 32 } else { 128 };
+    (0..=max_len).rev().find_map(|len| {
+        let prefix = NetworkPrefix { prefix: IpNet::new(addr, len).ok()?.trunc(), path_id: 0 };
+        peer_state.announcements().get_key_value(&prefix)
+    })
+}
+
+/// Answer a looking glass query.
+///
+/// Prefix queries are hash lookups per peer (one per prefix length for a longest match), so they do not scan the
+/// announcements. Only announcements without ADD-PATH path id are matched.
+pub fn looking_glass_query<S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, query: &LookingGlassQuery) -> LookingGlassResponse {
+    let results: Vec<LookingGlassResult> = match query {
+        LookingGlassQuery::PrefixExact(prefix) => sorted_peers(state)
+            .into_iter()
+            .filter_map(|(peer, peer_state)| peer_state.announcements().get_key_value(prefix).map(|found| route(peer, found)))
+            .collect(),
+        LookingGlassQuery::PrefixLongestMatch(addr) => sorted_peers(state)
+            .into_iter()
+            .filter_map(|(peer, peer_state)| longest_match(peer_state, *addr).map(|found| route(peer, found)))
+            .collect(),
+        LookingGlassQuery::AllPeers => sorted_peers(state)
+            .into_iter()
+            .map(|(peer, peer_state)| {
+                let (prefixes_v4, prefixes_v6) = peer_state.prefix_counts();
+                LookingGlassResult::Peer {
+                    peer_ip: peer.address,
+                    peer_asn: peer.peer_as,
+                    connection_state: peer_state.connection_state().clone(),
+                    prefixes_v4,
+                    prefixes_v6,
+                    last_message_timestamp: peer_state.last_message_timestamp(),
+                }
+            })
+            .collect(),
+        LookingGlassQuery::PeerDetail(peer) => state
+            .get_key_value(peer)
+            .map(|(peer, peer_state)| peer_state.sorted_announcements().into_iter().map(|found| route(peer, found)).collect())
+            .unwrap_or_default(),
+    };
+
+    LookingGlassResponse {
+        query_type: query.query_type(),
+        results,
+        total_peers: state.len(),
+        total_prefixes: state.values().map(|peer_state| peer_state.announcements().len()).sum(),
+    }
+}
+
+/// Write the answer to a looking glass query as a JSON object with the `query_type`, the matching `results` and the
+/// `total_peers` and `total_prefixes` of the state, see `looking_glass_query`.
+pub fn export_looking_glass_json<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, query: LookingGlassQuery, w: &mut W) -> Result<(), ProcessorError> {
+    serde_json::to_writer(&mut *w, &looking_glass_query(state, &query))?;
+    writeln!(w)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgpkit_parser::models::BgpElem;
+    use std::str::FromStr;
+
+    fn state() -> HashMap<BgpPeer, BgpState> {
+        let mut state = HashMap::new();
+        for (peer_ip, prefixes) in [("192.0.2.1", &["198.51.0.0/16", "198.51.100.0/24"][..]), ("192.0.2.2", &["198.51.0.0/16"][..])] {
+            let peer_state: &mut BgpState = state.entry(BgpPeer { address: IpAddr::from_str(peer_ip).unwrap(), peer_as: 65001 }).or_default();
+            for prefix in prefixes {
+                peer_state.update_prefix(BgpElem {
+                    timestamp: 1600000000.0,
+                    prefix: NetworkPrefix::from_str(prefix).unwrap(),
+                    ..Default::default()
+                });
+            }
+        }
+        state
+    }
+
+    fn query(query: LookingGlassQuery) -> serde_json::Value {
+        let mut out = Vec::new();
+        export_looking_glass_json(&state(), query, &mut out).unwrap();
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    fn matches(response: &serde_json::Value) -> Vec<(String, String)> {
+        response["results"].as_array().unwrap().iter()
+            .map(|result| (result["peer_ip"].as_str().unwrap().to_string(), result["prefix"].as_str().unwrap().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_looking_glass_queries() {
+        let response = query(LookingGlassQuery::PrefixLongestMatch(IpAddr::from_str("198.51.100.7").unwrap()));
+        assert_eq!(response["query_type"], "prefix_longest_match");
+        assert_eq!(response["total_peers"], 2);
+        assert_eq!(response["total_prefixes"], 3);
+        assert_eq!(matches(&response), vec![
+            ("192.0.2.1".to_string(), "198.51.100.0/24".to_string()),
+            ("192.0.2.2".to_string(), "198.51.0.0/16".to_string()),
+        ]);
+
+        let response = query(LookingGlassQuery::PrefixExact(NetworkPrefix::from_str("198.51.100.0/24").unwrap()));
+        assert_eq!(matches(&response), vec![("192.0.2.1".to_string(), "198.51.100.0/24".to_string())]);
+
+        let response = query(LookingGlassQuery::PeerDetail(BgpPeer { address: IpAddr::from_str("192.0.2.1").unwrap(), peer_as: 65001 }));
+        assert_eq!(matches(&response).len(), 2);
+
+        let response = query(LookingGlassQuery::AllPeers);
+        assert_eq!(response["results"][0]["prefixes_v4"], 2);
+        assert_eq!(response["results"][1]["connection_state"], "Idle");
+        assert!(query(LookingGlassQuery::PrefixLongestMatch(IpAddr::from_str("203.0.113.1").unwrap()))["results"].as_array().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+//! Exporters that write the processed BGP state to a sink.
+pub mod csv;
+pub mod influx;
+pub mod json;
+pub mod jsonl;
+pub mod looking_glass;
+pub mod mrt;
+pub mod openconfig;
+pub mod prometheus;
+pub mod yaml;
+
+use core::fmt;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::net::IpAddr;
+
+use bgpkit_parser::models::{AsPath, NetworkPrefix, Origin};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::bgp_state::{Announcement, BgpState, ConnectionState};
+use crate::error::ProcessorError;
+use crate::mrt_processor::{sorted_peers, BgpPeer};
+use crate::mrt_records::format_community;
+
+/// The supported output formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Json,
+    Jsonl,
+    Csv,
+    Yaml,
+    Prometheus,
+    Influx,
+    #[serde(rename = "openconfig")]
+    OpenConfig,
+    Parquet,
+    Sqlite,
+    Bird2,
+    Frr,
+    ExaBgp,
+}
+
+impl OutputFormat {
+    /// Whether `export_state` can write the format, the others are accepted in the config but not implemented yet
+    pub fn is_supported(&self) -> bool {
+        !matches!(self, OutputFormat::Parquet | OutputFormat::Sqlite | OutputFormat::Bird2 | OutputFormat::Frr | OutputFormat::ExaBgp)
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Jsonl => write!(f, "jsonl"),
+            OutputFormat::Csv => write!(f, "csv"),
+            OutputFormat::Yaml => write!(f, "yaml"),
+            OutputFormat::Prometheus => write!(f, "prometheus"),
+            OutputFormat::Influx => write!(f, "influx"),
+            OutputFormat::OpenConfig => write!(f, "openconfig"),
+            OutputFormat::Parquet => write!(f, "parquet"),
+            OutputFormat::Sqlite => write!(f, "sqlite"),
+            OutputFormat::Bird2 => write!(f, "bird2"),
+            OutputFormat::Frr => write!(f, "frr"),
+            OutputFormat::ExaBgp => write!(f, "exabgp"),
+        }
+    }
+}
+
+/// Write the state in the given format
+pub fn export_state<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, format: OutputFormat, w: &mut W) -> Result<(), ProcessorError> {
+    match format {
+        OutputFormat::Json => json::export_json(state, w),
+        OutputFormat::Jsonl => jsonl::export_jsonl(state, w),
+        OutputFormat::Csv => csv::export_csv(state, w),
+        OutputFormat::Yaml => yaml::export_yaml(state, w),
+        OutputFormat::Prometheus => prometheus::export_prometheus(state, w),
+        OutputFormat::Influx => influx::export_influx(state, w),
+        OutputFormat::OpenConfig => openconfig::export_openconfig_bgp_rib(state, w),
+        OutputFormat::Parquet | OutputFormat::Sqlite | OutputFormat::Bird2 | OutputFormat::Frr | OutputFormat::ExaBgp => {
+            Err(ProcessorError::ExportError(format!("Output format {format} is not supported yet.")))
+        },
+    }
+}
+
+/// Serializable view of a single peer and its announcements
+#[derive(Serialize)]
+pub(crate) struct PeerRecord<'a> {
+    peer_ip: IpAddr,
+    peer_asn: u32,
+    connection_state: &'a ConnectionState,
+    hold_time: Option<u16>,
+    last_message_timestamp: Option<DateTime<Utc>>,
+    first_seen: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+    announcements: Vec<AnnouncementRecord<'a>>,
+}
+
+/// Serializable view of an announcement together with its prefix, communities use `format_community`
+#[derive(Serialize)]
+pub(crate) struct AnnouncementRecord<'a> {
+    prefix: &'a NetworkPrefix,
+    timestamp: DateTime<Utc>,
+    as_path: &'a Option<AsPath>,
+    origin: &'a Option<Origin>,
+    local_pref: Option<u32>,
+    next_hop: Option<IpAddr>,
+    med: Option<u32>,
+    communities: Option<Vec<String>>,
+    only_to_customer: Option<u32>,
+    aggregator: Option<(u32, IpAddr)>,
+}
+
+impl<'a> AnnouncementRecord<'a> {
+    pub(crate) fn new(prefix: &'a NetworkPrefix, announcement: &'a Announcement) -> Self {
+        AnnouncementRecord {
+            prefix,
+            timestamp: announcement.timestamp,
+            as_path: &announcement.as_path,
+            origin: &announcement.origin,
+            local_pref: announcement.local_pref,
+            next_hop: announcement.next_hop,
+            med: announcement.med,
+            communities: announcement.communities.as_ref().map(|communities| communities.iter().map(format_community).collect()),
+            only_to_customer: announcement.only_to_customer,
+            aggregator: announcement.aggregator,
+        }
+    }
+}
+
+/// Serializable views of all peers, in canonical order
+pub(crate) fn peer_records<S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>) -> Vec<PeerRecord<'_>> {
+    sorted_peers(state).into_iter().map(|(peer, peer_state)| PeerRecord {
+        peer_ip: peer.address,
+        peer_asn: peer.peer_as,
+        connection_state: peer_state.connection_state(),
+        hold_time: peer_state.hold_time(),
+        last_message_timestamp: peer_state.last_message_timestamp(),
+        first_seen: peer_state.first_seen(),
+        last_seen: peer_state.last_seen(),
+        announcements: peer_state.sorted_announcements()
+            .into_iter()
+            .map(|(prefix, announcement)| AnnouncementRecord::new(prefix, announcement))
+            .collect(),
+    }).collect()
+}
@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+use bgpkit_parser::models::{
+    Attributes, BgpElem, CommonHeader, EntryType, MrtMessage, NetworkPrefix, Peer, PeerIndexTable, RibAfiEntries, RibEntry,
+    TableDumpV2Message, TableDumpV2Type,
+};
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+
+use crate::bgp_state::{Announcement, BgpState};
+use crate::error::ProcessorError;
+use crate::mrt_processor::{sorted_peers, BgpPeer};
+
+/// Write the announcements of all peers as a TABLE_DUMP_V2 bview that `MrtProcessor::process_bview` can read back.
+///
+/// The file holds a PEER_INDEX_TABLE with every peer of the state, also those without announcements, followed by one
+/// RIB_IPV4_UNICAST or RIB_IPV6_UNICAST record per prefix ordered by prefix, all with timestamp `ts`. The announcement
+/// timestamps are kept as the originated time of the RIB entries, note that `bgpkit_parser` reports the record
+/// timestamp for RIB entries, so the announcements read back are all at `ts`.
+pub fn export_as_bview<P: AsRef<Path>, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, path: P, ts: DateTime<Utc>) -> Result<(), ProcessorError> {
+    let mut index_table = PeerIndexTable::default();
+    // Ordered by prefix, IPv4 before IPv6
+    let mut ribs: BTreeMap<IpNet, Vec<RibEntry>> = BTreeMap::new();
+
+    for (peer_index, (peer, peer_state)) in sorted_peers(state).into_iter().enumerate() {
+        // Peers are indexed by position, `PeerIndexTable::add_peer` would merge peers that share an address
+        let peer_index = peer_index as u16;
+        let bgp_identifier = match peer.address {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+        };
+        index_table.id_peer_map.insert(peer_index, Peer::new(bgp_identifier, peer.address, peer.peer_as.into()));
+        index_table.peer_addr_id_map.entry(peer.address).or_insert(peer_index);
+
+        for (prefix, ann) in peer_state.sorted_announcements() {
+            ribs.entry(prefix.prefix).or_default().push(RibEntry {
+                peer_index,
+                originated_time: ann.timestamp.timestamp() as u32,
+                attributes: Attributes::from(&to_elem(peer, prefix, ann)),
+            });
+        }
+    }
+
+    let timestamp = ts.timestamp() as u32;
+    let mut bytes = BytesMut::new();
+    encode_record(&mut bytes, timestamp, TableDumpV2Type::PeerIndexTable, TableDumpV2Message::PeerIndexTable(index_table));
+    for (sequence_number, (prefix, rib_entries)) in ribs.into_iter().enumerate() {
+        let rib_type = match prefix.addr() {
+            IpAddr::V4(_) => TableDumpV2Type::RibIpv4Unicast,
+            IpAddr::V6(_) => TableDumpV2Type::RibIpv6Unicast,
+        };
+        let rib = RibAfiEntries { rib_type, sequence_number: sequence_number as u32, prefix: NetworkPrefix::new(prefix, 0), rib_entries };
+        encode_record(&mut bytes, timestamp, rib_type, TableDumpV2Message::RibAfi(rib));
+    }
+
+    std::fs::write(path.as_ref(), bytes)
+        .map_err(|e| ProcessorError::ExportError(format!("Failed to write {}: {}", path.as_ref().display(), e)))
+}
+
+// Append a TABLE_DUMP_V2 record with its common header
+fn encode_record(bytes: &mut BytesMut, timestamp: u32, subtype: TableDumpV2Type, message: TableDumpV2Message) {
+    let data = MrtMessage::TableDumpV2Message(message).encode(subtype as u16);
+    let header = CommonHeader {
+        timestamp,
+        microsecond_timestamp: None,
+        entry_type: EntryType::TABLE_DUMP_V2,
+        entry_subtype: subtype as u16,
+        length: data.len() as u32,
+    };
+    bytes.extend_from_slice(&header.encode());
+    bytes.extend_from_slice(&data);
+}
+
+// RIB entry of an announcement
+fn to_elem(peer: &BgpPeer, prefix: &NetworkPrefix, ann: &Announcement) -> BgpElem {
+    let (aggr_asn, aggr_ip) = match ann.aggregator {
+        Some((asn, IpAddr::V4(ip))) => (Some(asn.into()), Some(ip)),
+        _ => (None, None),
+    };
+
+    BgpElem {
+        timestamp: ann.timestamp.timestamp() as f64,
+        peer_ip: peer.address,
+        peer_asn: peer.peer_as.into(),
+        prefix: *prefix,
+        next_hop: ann.next_hop,
+        as_path: ann.as_path.clone(),
+        origin: ann.origin,
+        local_pref: ann.local_pref,
+        med: ann.med,
+        communities: ann.communities.clone(),
+        only_to_customer: ann.only_to_customer.map(Into::into),
+        aggr_asn,
+        aggr_ip,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mrt_processor::MrtProcessor;
+    use bgpkit_parser::models::{AsPath, Community, MetaCommunity, Origin};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_export_as_bview_roundtrip() {
+        let mut state = HashMap::new();
+        for (address, peer_as, prefix, next_hop) in [
+            ("192.0.2.1", 65001, "198.51.100.0/24", "192.0.2.1"),
+            ("192.0.2.1", 65001, "203.0.113.0/24", "192.0.2.1"),
+            ("2001:db8::1", 65002, "2001:db8:1::/48", "2001:db8::1"),
+        ] {
+            let peer = BgpPeer { address: IpAddr::from_str(address).unwrap(), peer_as };
+            let peer_state: &mut BgpState = state.entry(peer).or_default();
+            peer_state.update_prefix(BgpElem {
+                timestamp: 1600000000.0,
+                prefix: NetworkPrefix::from_str(prefix).unwrap(),
+                next_hop: Some(IpAddr::from_str(next_hop).unwrap()),
+                as_path: Some(AsPath::from_sequence([peer_as, 3356, 65010])),
+                origin: Some(Origin::IGP),
+                local_pref: Some(100),
+                med: Some(10),
+                communities: Some(vec![MetaCommunity::Plain(Community::Custom(3356.into(), 100))]),
+                ..Default::default()
+            });
+        }
+
+        // A peer without announcements and a peer sharing an address with another one are kept in the index table
+        state.insert(BgpPeer { address: IpAddr::from_str("192.0.2.2").unwrap(), peer_as: 65003 }, BgpState::default());
+        state.insert(BgpPeer { address: IpAddr::from_str("192.0.2.1").unwrap(), peer_as: 65101 }, BgpState::default());
+
+        let path = std::env::temp_dir().join(format!("mrt_state_to_state_export_bview_{}.mrt", std::process::id()));
+        export_as_bview(&state, &path, DateTime::from_timestamp(1600000300, 0).unwrap()).unwrap();
+        let mut processor = MrtProcessor::default();
+        processor.process_bview(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(processor.last_processed_timestamp(), DateTime::from_timestamp(1600000300, 0));
+        let loaded = processor.get_current_state();
+        assert_eq!(loaded.len(), state.len());
+        for (peer, peer_state) in &state {
+            let loaded_announcements = loaded[peer].announcements();
+            assert_eq!(loaded_announcements.len(), peer_state.announcements().len());
+            for (prefix, ann) in peer_state.announcements() {
+                let loaded_ann = &loaded_announcements[prefix];
+                assert!(ann.same_attributes(loaded_ann), "{:?} != {:?}", ann, loaded_ann);
+                assert_eq!(loaded_ann.timestamp, DateTime::from_timestamp(1600000300, 0).unwrap());
+            }
+        }
+    }
+}
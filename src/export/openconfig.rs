@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::net::IpAddr;
+
+use bgpkit_parser::models::{AsPath, AsPathSegment, NetworkPrefix};
+use serde::Serialize;
+
+use crate::bgp_state::{Announcement, BgpState};
+use crate::error::ProcessorError;
+use crate::mrt_processor::{sorted_peers, BgpPeer};
+use crate::mrt_records::format_community;
+use crate::util::prefix_sort_key;
+
+/// Top level container of the `openconfig-rib-bgp` module in JSON IETF encoding
+#[derive(Serialize)]
+struct Document {
+    #[serde(rename = "openconfig-rib-bgp:bgp-rib")]
+    bgp_rib: BgpRib,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct BgpRib {
+    attr_sets: AttrSets,
+    communities: Communities,
+    afi_safis: AfiSafis,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct AttrSets {
+    attr_set: Vec<Indexed<AttrSetState>>,
+}
+
+#[derive(Serialize)]
+struct Communities {
+    community: Vec<Indexed<CommunitySetState>>,
+}
+
+/// List entry keyed by `index`, the key is repeated in the state container
+#[derive(Serialize)]
+struct Indexed<T> {
+    index: usize,
+    state: T,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct AttrSetState {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_hop: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    med: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_pref: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    as_path: Option<AsPathContainer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregator: Option<AggregatorContainer>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct AsPathContainer {
+    as_segment: Vec<AsSegment>,
+}
+
+#[derive(Serialize)]
+struct AsSegment {
+    state: AsSegmentState,
+}
+
+#[derive(Serialize)]
+struct AsSegmentState {
+    #[serde(rename = "type")]
+    segment_type: &'static str,
+    member: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct AggregatorContainer {
+    state: AggregatorState,
+}
+
+#[derive(Serialize)]
+struct AggregatorState {
+    #[serde(rename = "as")]
+    asn: u32,
+    address: IpAddr,
+}
+
+#[derive(Serialize)]
+struct CommunitySetState {
+    index: usize,
+    community: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct AfiSafis {
+    afi_safi: Vec<AfiSafi>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct AfiSafi {
+    afi_safi_name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv4_unicast: Option<Neighbors>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_unicast: Option<Neighbors>,
+}
+
+#[derive(Serialize)]
+struct Neighbors {
+    neighbors: NeighborList,
+}
+
+#[derive(Serialize)]
+struct NeighborList {
+    neighbor: Vec<Neighbor>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Neighbor {
+    neighbor_address: IpAddr,
+    adj_rib_in_post: Routes,
+}
+
+#[derive(Serialize)]
+struct Routes {
+    routes: RouteList,
+}
+
+#[derive(Serialize)]
+struct RouteList {
+    route: Vec<Route>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Route {
+    prefix: String,
+    path_id: u32,
+    state: RouteState,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct RouteState {
+    prefix: String,
+    path_id: u32,
+    attr_index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    community_index: Option<usize>,
+}
+
+/// Attribute and community sets shared by the routes, deduplicated by content
+#[derive(Default)]
+struct SharedSets {
+    attr_sets: Vec<Indexed<AttrSetState>>,
+    attr_set_index: HashMap<String, usize>,
+    community_sets: Vec<Indexed<CommunitySetState>>,
+    community_set_index: HashMap<Vec<String>, usize>,
+}
+
+impl SharedSets {
+    fn attr_index(&mut self, ann: &Announcement) -> Result<usize, serde_json::Error> {
+        let mut attr_set = AttrSetState {
+            index: 0,
+            origin: ann.origin.map(|origin| origin.to_string()),
+            next_hop: ann.next_hop,
+            med: ann.med,
+            local_pref: ann.local_pref,
+            as_path: ann.as_path.as_ref().map(as_path_container),
+            aggregator: ann.aggregator.map(|(asn, address)| AggregatorContainer { state: AggregatorState { asn, address } }),
+        };
+        let key = serde_json::to_string(&attr_set)?;
+        if let Some(index) = self.attr_set_index.get(&key) {
+            return Ok(*index);
+        }
+
+        let index = self.attr_sets.len();
+        attr_set.index = index;
+        self.attr_sets.push(Indexed { index, state: attr_set });
+        self.attr_set_index.insert(key, index);
+        Ok(index)
+    }
+
+    fn community_index(&mut self, ann: &Announcement) -> Option<usize> {
+        let communities: Vec<String> = ann.communities.as_ref()?.iter().map(format_community).collect();
+        if communities.is_empty() {
+            return None;
+        }
+        if let Some(index) = self.community_set_index.get(&communities) {
+            return Some(*index);
+        }
+
+        let index = self.community_sets.len();
+        self.community_sets.push(Indexed { index, state: CommunitySetState { index, community: communities.clone() } });
+        self.community_set_index.insert(communities, index);
+        Some(index)
+    }
+}
+
+fn as_path_container(path: &AsPath) -> AsPathContainer {
+    let as_segment = path.iter_segments().map(|segment| {
+        let segment_type = match segment {
+            AsPathSegment::AsSequence(_) => "AS_SEQ",
+            AsPathSegment::AsSet(_) => "AS_SET",
+            AsPathSegment::ConfedSequence(_) => "AS_CONFED_SEQUENCE",
+            AsPathSegment::ConfedSet(_) => "AS_CONFED_SET",
+        };
+        AsSegment { state: AsSegmentState { segment_type, member: segment.iter().map(|asn| asn.to_u32()).collect() } }
+    }).collect();
+    AsPathContainer { as_segment }
+}
+
+fn route(prefix: &NetworkPrefix, attr_index: usize, community_index: Option<usize>) -> Route {
+    Route {
+        prefix: prefix.prefix.to_string(),
+        path_id: prefix.path_id,
+        state: RouteState { prefix: prefix.prefix.to_string(), path_id: prefix.path_id, attr_index, community_index },
+    }
+}
+
+/// Write the state as the Adj-RIB-In (post-policy) of every peer following the `openconfig-rib-bgp` YANG model.
+///
+/// Routes reference their attributes by `attr-index` into `attr-sets` and their communities by `community-index`
+/// into `communities` (the YANG name of the community sets), routes with the same attributes share an entry.
+/// Neighbors are keyed by address alone, so peers sharing an address with different ASNs (e.g. across collectors or
+/// after an ASN change) are merged into one neighbor. For a prefix announced by more than one of them the most recent
+/// announcement is exported. Neighbors are ordered by address and routes by prefix.
+pub fn export_openconfig_bgp_rib<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, w: &mut W) -> Result<(), ProcessorError> {
+    let mut sets = SharedSets::default();
+    let mut ipv4_neighbors = Vec::new();
+    let mut ipv6_neighbors = Vec::new();
+
+    // `neighbor-address` is the list key, merge the routes of peers with the same address
+    let mut neighbor_routes: BTreeMap<IpAddr, HashMap<&NetworkPrefix, &Announcement>> = BTreeMap::new();
+    for (peer, peer_state) in sorted_peers(state) {
+        let routes = neighbor_routes.entry(peer.address).or_default();
+        for (prefix, ann) in peer_state.announcements() {
+            routes.entry(prefix)
+                .and_modify(|existing| if ann.timestamp > existing.timestamp { *existing = ann })
+                .or_insert(ann);
+        }
+    }
+
+    for (address, routes) in neighbor_routes {
+        let mut routes: Vec<(&NetworkPrefix, &Announcement)> = routes.into_iter().collect();
+        routes.sort_by_key(|(prefix, _)| (prefix_sort_key(prefix), prefix.path_id));
+
+        let mut ipv4_routes = Vec::new();
+        let mut ipv6_routes = Vec::new();
+        for (prefix, ann) in routes {
+            let route = route(prefix, sets.attr_index(ann)?, sets.community_index(ann));
+            match prefix.prefix.addr() {
+                IpAddr::V4(_) => ipv4_routes.push(route),
+                IpAddr::V6(_) => ipv6_routes.push(route),
+            }
+        }
+
+        for (routes, neighbors) in [(ipv4_routes, &mut ipv4_neighbors), (ipv6_routes, &mut ipv6_neighbors)] {
+            if !routes.is_empty() {
+                neighbors.push(Neighbor { neighbor_address: address, adj_rib_in_post: Routes { routes: RouteList { route: routes } } });
+            }
+        }
+    }
+
+    let document = Document {
+        bgp_rib: BgpRib {
+            attr_sets: AttrSets { attr_set: sets.attr_sets },
+            communities: Communities { community: sets.community_sets },
+            afi_safis: AfiSafis {
+                afi_safi: vec![
+                    AfiSafi {
+                        afi_safi_name: "openconfig-bgp-types:IPV4_UNICAST",
+                        ipv4_unicast: Some(Neighbors { neighbors: NeighborList { neighbor: ipv4_neighbors } }),
+                        ipv6_unicast: None,
+                    },
+                    AfiSafi {
+                        afi_safi_name: "openconfig-bgp-types:IPV6_UNICAST",
+                        ipv4_unicast: None,
+                        ipv6_unicast: Some(Neighbors { neighbors: NeighborList { neighbor: ipv6_neighbors } }),
+                    },
+                ],
+            },
+        },
+    };
+    serde_json::to_writer_pretty(&mut *w, &document)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgpkit_parser::models::{BgpElem, Community, MetaCommunity, Origin};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_export_openconfig_bgp_rib() {
+        let mut state: HashMap<BgpPeer, BgpState> = HashMap::new();
+        for (address, prefix, communities) in [
+            ("192.0.2.1", "198.51.100.0/24", vec![MetaCommunity::Plain(Community::NoExport)]),
+            ("192.0.2.1", "203.0.113.0/24", vec![MetaCommunity::Plain(Community::NoExport)]),
+            ("192.0.2.2", "2001:db8::/32", vec![]),
+        ] {
+            let peer = BgpPeer { address: IpAddr::from_str(address).unwrap(), peer_as: 65001 };
+            state.entry(peer).or_default().update_prefix(BgpElem {
+                timestamp: 1600000000.0,
+                prefix: NetworkPrefix::from_str(prefix).unwrap(),
+                as_path: Some(AsPath::from_sequence([65001, 65002])),
+                origin: Some(Origin::IGP),
+                communities: Some(communities),
+                ..Default::default()
+            });
+        }
+
+        let mut out = Vec::new();
+        export_openconfig_bgp_rib(&state, &mut out).unwrap();
+        let doc: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let rib = &doc["openconfig-rib-bgp:bgp-rib"];
+
+        // Both IPv4 routes share the attribute and community set, the IPv6 route has no communities
+        assert_eq!(rib["attr-sets"]["attr-set"].as_array().unwrap().len(), 1);
+        assert_eq!(rib["attr-sets"]["attr-set"][0]["state"]["as-path"]["as-segment"][0]["state"]["member"], serde_json::json!([65001, 65002]));
+        assert_eq!(rib["communities"]["community"], serde_json::json!([{"index": 0, "state": {"index": 0, "community": ["NO_EXPORT"]}}]));
+
+        let ipv4 = &rib["afi-safis"]["afi-safi"][0]["ipv4-unicast"]["neighbors"]["neighbor"];
+        assert_eq!(ipv4[0]["neighbor-address"], "192.0.2.1");
+        assert_eq!(
+            ipv4[0]["adj-rib-in-post"]["routes"]["route"][1],
+            serde_json::json!({"prefix": "203.0.113.0/24", "path-id": 0, "state": {"prefix": "203.0.113.0/24", "path-id": 0, "attr-index": 0, "community-index": 0}})
+        );
+        let ipv6 = &rib["afi-safis"]["afi-safi"][1]["ipv6-unicast"]["neighbors"]["neighbor"];
+        assert_eq!(ipv6[0]["adj-rib-in-post"]["routes"]["route"][0]["state"], serde_json::json!({"prefix": "2001:db8::/32", "path-id": 0, "attr-index": 0}));
+    }
+
+    #[test]
+    fn test_export_openconfig_shared_neighbor_address() {
+        let announce = |ts: f64, prefix: &str, origin: u32| BgpElem {
+            timestamp: ts,
+            prefix: NetworkPrefix::from_str(prefix).unwrap(),
+            as_path: Some(AsPath::from_sequence([origin])),
+            ..Default::default()
+        };
+        // The peer changed its ASN from 65001 to 65101, 198.51.100.0/24 was announced again after the change
+        let address = IpAddr::from_str("192.0.2.1").unwrap();
+        let mut state: HashMap<BgpPeer, BgpState> = HashMap::new();
+        let old = state.entry(BgpPeer { address, peer_as: 65001 }).or_default();
+        old.update_prefix(announce(1600000000.0, "198.51.100.0/24", 65001));
+        old.update_prefix(announce(1600000000.0, "203.0.113.0/24", 65001));
+        state.entry(BgpPeer { address, peer_as: 65101 }).or_default().update_prefix(announce(1600000100.0, "198.51.100.0/24", 65101));
+
+        let mut out = Vec::new();
+        export_openconfig_bgp_rib(&state, &mut out).unwrap();
+        let doc: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let rib = &doc["openconfig-rib-bgp:bgp-rib"];
+
+        let neighbors = rib["afi-safis"]["afi-safi"][0]["ipv4-unicast"]["neighbors"]["neighbor"].as_array().unwrap();
+        assert_eq!(neighbors.len(), 1);
+        let routes = neighbors[0]["adj-rib-in-post"]["routes"]["route"].as_array().unwrap();
+        assert_eq!(routes.iter().map(|route| route["prefix"].as_str().unwrap()).collect::<Vec<_>>(), vec!["198.51.100.0/24", "203.0.113.0/24"]);
+        let attr_index = routes[0]["state"]["attr-index"].as_u64().unwrap() as usize;
+        assert_eq!(rib["attr-sets"]["attr-set"][attr_index]["state"]["as-path"]["as-segment"][0]["state"]["member"], serde_json::json!([65101]));
+    }
+}
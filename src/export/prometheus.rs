@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+
+use crate::bgp_state::{BgpState, ConnectionState};
+use crate::error::ProcessorError;
+use crate::mrt_processor::{sorted_peers, BgpPeer};
+
+/// Write per-peer gauges in the Prometheus text exposition format
+pub fn export_prometheus<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, w: &mut W) -> Result<(), ProcessorError> {
+    writeln!(w, "# HELP bgp_peer_established Whether the BGP session is in the Established state.")?;
+    writeln!(w, "# TYPE bgp_peer_established gauge")?;
+    for (peer, peer_state) in sorted_peers(state) {
+        let established = (*peer_state.connection_state() == ConnectionState::Established) as u8;
+        writeln!(w, "bgp_peer_established{{peer_ip=\"{}\",peer_asn=\"{}\"}} {}", peer.address, peer.peer_as, established)?;
+    }
+
+    writeln!(w, "# HELP bgp_peer_prefixes Number of prefixes announced by the peer.")?;
+    writeln!(w, "# TYPE bgp_peer_prefixes gauge")?;
+    for (peer, peer_state) in sorted_peers(state) {
+        let (v4, v6) = peer_state.prefix_counts();
+        writeln!(w, "bgp_peer_prefixes{{peer_ip=\"{}\",peer_asn=\"{}\",afi=\"ipv4\"}} {}", peer.address, peer.peer_as, v4)?;
+        writeln!(w, "bgp_peer_prefixes{{peer_ip=\"{}\",peer_asn=\"{}\",afi=\"ipv6\"}} {}", peer.address, peer.peer_as, v6)?;
+    }
+
+    Ok(())
+}
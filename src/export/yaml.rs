@@ -0,0 +1,14 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
+
+use crate::bgp_state::BgpState;
+use crate::error::ProcessorError;
+use crate::export::peer_records;
+use crate::mrt_processor::BgpPeer;
+
+/// Write the state as a YAML sequence with one entry per peer
+pub fn export_yaml<W: Write, S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>, w: &mut W) -> Result<(), ProcessorError> {
+    serde_yaml::to_writer(w, &peer_records(state))?;
+    Ok(())
+}
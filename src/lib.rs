@@ -0,0 +1,13 @@
+pub mod analysis;
+pub mod announcement;
+pub mod bgp_state;
+pub mod checkpoint;
+pub mod error;
+pub mod export;
+pub mod mrt_processor;
+pub mod mrt_reader;
+pub mod mrt_records;
+#[cfg(feature = "tower-service")]
+pub mod service;
+pub mod topology;
+pub mod util;
@@ -1,39 +1,288 @@
-mod bgp_state;
-mod mrt_processor;
-mod util;
-
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::Read;
+use clap::{Parser, ValueEnum};
+use comfy_table::Table;
+use mrt_state_to_state::error::ProcessorError;
+use mrt_state_to_state::export::{export_state, jsonl::export_jsonl, OutputFormat};
+use mrt_state_to_state::mrt_processor::{MergeMode, MrtProcessor, PeerSummaryRow, DEFAULT_DIRECTORY_PATTERN};
+use mrt_state_to_state::mrt_records::load_remap_table;
+use mrt_state_to_state::util::{first_record_timestamp, format_prefix, list_mrt_files};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
 
 /// Command line arguments
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to config file
+    /// Path to config file, `env` reads the configuration from `MRT_*` environment variables
     #[arg(short, long, default_value = "config.yaml")]
     config: String,
+
+    /// Hold time in seconds for peers without an OPEN message, overrides the config
+    #[arg(long)]
+    default_hold_time: Option<u16>,
+
+    /// Multiple of the hold time after which a silent peer is reset, overrides the config
+    #[arg(long)]
+    send_hold_time_multiple: Option<u16>,
+
+    /// Only keep the first N peers seen, e.g. for quickly testing a pipeline on a large bview
+    #[arg(long)]
+    max_peers: Option<usize>,
+
+    /// Print the final state to stdout in this format
+    #[arg(long, value_enum)]
+    output_format: Option<CliOutputFormat>,
+
+    /// Sort order of the peer summary table
+    #[arg(long, value_enum, default_value_t = SortBy::Ip)]
+    sort_by: SortBy,
+
+    /// Load this checkpoint instead of the initial states and skip the update files it already covers
+    #[arg(long)]
+    resume_from: Option<String>,
+}
+
+/// Output formats that can be selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CliOutputFormat {
+    /// Human readable peer summary table
+    Table,
+    /// One JSON object per announcement and line
+    Jsonl,
+}
+
+/// Sort order of the peer summary table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortBy {
+    Ip,
+    Asn,
+    PrefixCount,
+    State,
 }
 
 // Define a struct that represents your YAML data structure
 #[derive(Debug, Serialize, Deserialize)]
 struct Config {
-    initial_state: Option<String>,
+    /// Bviews to load before the update files, `initial_state` with a single path is still accepted
+    #[serde(default, alias = "initial_state", deserialize_with = "one_or_many")]
+    initial_states: Option<Vec<String>>,
+    /// State written by `MrtProcessor::save_state_json`, merged into the state after the bviews
+    initial_state_json: Option<String>,
+    /// How bviews after the first one are merged into the state
+    #[serde(default = "default_bview_merge_mode")]
+    bview_merge_mode: MergeMode,
+    /// Update files, directories are replaced by the `*.gz` files they contain in lexicographic order. Bviews among them
+    /// replace the state.
     update_files: Vec<String>,
+    output: Option<OutputConfig>,
+    /// Write a checkpoint after every N update files
+    checkpoint_interval: Option<usize>,
+    /// Directory for checkpoints, defaults to the working directory
+    checkpoint_dir: Option<String>,
+    /// Hold time in seconds for peers without an OPEN message
+    default_hold_time: Option<u16>,
+    /// Multiple of the hold time after which a silent peer is reset
+    send_hold_time_multiple: Option<u16>,
+    /// CSV file with `old_asn,new_asn` lines, the ASN mapped to AS_TRANS replaces the AS_TRANS hops of AS paths
+    asn_remap_table: Option<String>,
+    /// Log data errors such as prefixes with host bits set at warn instead of debug level
+    #[serde(default)]
+    strict: bool,
+}
+
+const DEFAULT_HOLD_TIME: u16 = 180;
+const DEFAULT_SEND_HOLD_TIME_MULTIPLE: u16 = 3;
+/// Updates per prefix in the last minute above which the prefix is reported as oscillating
+const OSCILLATION_THRESHOLD: u32 = 10;
+
+impl Config {
+    /// Build the configuration from the `MRT_*` environment variables
+    fn from_env() -> Result<Config, ProcessorError> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Config, ProcessorError> {
+        let update_files = lookup("MRT_UPDATE_FILES")
+            .ok_or_else(|| ProcessorError::ConfigError("Environment variable MRT_UPDATE_FILES (colon-separated update files) is not set".to_string()))?;
+
+        let mut config = Config {
+            initial_states: None,
+            initial_state_json: None,
+            bview_merge_mode: default_bview_merge_mode(),
+            update_files: split_paths(&update_files),
+            output: None,
+            checkpoint_interval: None,
+            checkpoint_dir: None,
+            default_hold_time: None,
+            send_hold_time_multiple: None,
+            asn_remap_table: None,
+            strict: false,
+        };
+        config.fill_from_lookup(lookup)?;
+        Ok(config)
+    }
+
+    /// Reject settings that would only fail while or after the files are processed
+    fn validate(&self) -> Result<(), ProcessorError> {
+        if let Some(output) = &self.output
+            && !output.format.is_supported() {
+            return Err(ProcessorError::ConfigError(format!("Output format {} is not supported yet", output.format)));
+        }
+        if self.checkpoint_interval == Some(0) {
+            return Err(ProcessorError::ConfigError("checkpoint_interval must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Fill the optional fields that are not set from the environment variables
+    fn fill_from_env(&mut self) -> Result<(), ProcessorError> {
+        self.fill_from_lookup(|name| std::env::var(name).ok())
+    }
+
+    fn fill_from_lookup(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<(), ProcessorError> {
+        let parse_u16 = |name: &str| -> Result<Option<u16>, ProcessorError> {
+            lookup(name)
+                .map(|value| value.parse::<u16>().map_err(|e| ProcessorError::ConfigError(format!("Environment variable {name} is not a valid number ({value:?}): {e}"))))
+                .transpose()
+        };
+
+        if self.initial_states.is_none() {
+            self.initial_states = lookup("MRT_INITIAL_STATE").map(|value| split_paths(&value));
+        }
+        if self.default_hold_time.is_none() {
+            self.default_hold_time = parse_u16("MRT_DEFAULT_HOLD_TIME")?;
+        }
+        if self.send_hold_time_multiple.is_none() {
+            self.send_hold_time_multiple = parse_u16("MRT_SEND_HOLD_TIME_MULTIPLE")?;
+        }
+        Ok(())
+    }
+}
+
+// Replace directories by the MRT files they contain, in lexicographic order
+fn expand_update_files(entries: &[String]) -> Result<Vec<String>, ProcessorError> {
+    let mut files = Vec::new();
+    for entry in entries {
+        if Path::new(entry).is_dir() {
+            files.extend(list_mrt_files(entry, DEFAULT_DIRECTORY_PATTERN)?.iter().map(|path| path.display().to_string()));
+        } else {
+            files.push(entry.clone());
+        }
+    }
+    Ok(files)
+}
+
+// Split a colon-separated list of paths
+fn split_paths(value: &str) -> Vec<String> {
+    value.split(':').filter(|path| !path.is_empty()).map(str::to_string).collect()
+}
+
+fn default_bview_merge_mode() -> MergeMode {
+    MergeMode::Incremental
+}
+
+// Accept either a single string or a list of strings
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(Option::<OneOrMany>::deserialize(deserializer)?.map(|value| match value {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    }))
+}
+
+/// Where and how to write the final state
+#[derive(Debug, Serialize, Deserialize)]
+struct OutputConfig {
+    format: OutputFormat,
+    /// Output path, `-` writes to stdout
+    path: String,
+    /// Append to the output file instead of truncating it
+    #[serde(default)]
+    append: bool,
+}
+
+// Open the output sink described by the output config
+fn open_output(output: &OutputConfig) -> Result<Box<dyn Write>, ProcessorError> {
+    if output.path == "-" {
+        return Ok(Box::new(std::io::stdout().lock()));
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(output.append)
+        .truncate(!output.append)
+        .open(&output.path)?;
+    Ok(Box::new(std::io::BufWriter::new(file)))
 }
 
 // Function to load config from YAML file
-fn load_config(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
-    let mut file = File::open(path)?;
+// Values missing from the file are taken from the environment, without a file the environment is used.
+fn load_config(path: &str) -> Result<Config, ProcessorError> {
+    if path == "env" {
+        return Config::from_env();
+    }
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::info!("Config file {} not found, reading configuration from environment variables.", path);
+            return Config::from_env();
+        },
+        Err(e) => return Err(e.into()),
+    };
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
 
-    let config: Config = serde_yaml::from_str(&contents)?;
+    let mut config: Config = serde_yaml::from_str(&contents)
+        .map_err(|e| ProcessorError::ConfigError(format!("Failed to parse {}: {}", path, e)))?;
+    config.fill_from_env()?;
+    config.validate()?;
     Ok(config)
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+// Render the peer summary as an ASCII table with a footer containing the prefix totals
+fn peer_summary_table(mut rows: Vec<PeerSummaryRow>, sort_by: SortBy) -> Table {
+    match sort_by {
+        SortBy::Ip => rows.sort_by_key(|row| (row.peer_ip, row.peer_asn)),
+        SortBy::Asn => rows.sort_by_key(|row| (row.peer_asn, row.peer_ip)),
+        SortBy::PrefixCount => rows.sort_by_key(|row| (std::cmp::Reverse(row.prefixes_v4 + row.prefixes_v6), row.peer_ip)),
+        SortBy::State => rows.sort_by(|a, b| a.state.cmp(&b.state).then(a.peer_ip.cmp(&b.peer_ip))),
+    }
+
+    let mut table = Table::new();
+    table.set_header(vec!["Peer IP", "Peer ASN", "State", "Prefixes (v4)", "Prefixes (v6)", "Hold time", "Last message"]);
+    for row in &rows {
+        table.add_row(vec![
+            row.peer_ip.to_string(),
+            row.peer_asn.to_string(),
+            row.state.to_string(),
+            row.prefixes_v4.to_string(),
+            row.prefixes_v6.to_string(),
+            row.hold_time.map(|t| t.to_string()).unwrap_or_default(),
+            row.last_message_timestamp.map(|ts| ts.to_string()).unwrap_or_default(),
+        ]);
+    }
+    table.add_row(vec![
+        "Total".to_string(),
+        String::new(),
+        String::new(),
+        rows.iter().map(|row| row.prefixes_v4).sum::<usize>().to_string(),
+        rows.iter().map(|row| row.prefixes_v6).sum::<usize>().to_string(),
+        String::new(),
+        String::new(),
+    ]);
+    table
+}
+
+fn main() -> Result<(), ProcessorError> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     // Parse command line arguments
@@ -45,13 +294,152 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     log::info!("Loaded configuration from: {}", args.config);
     log::debug!("Config: {:?}", config);
 
-    let mut processor = mrt_processor::MrtProcessor::new(180,Some(3));
-    config.initial_state.map(|file| processor.process_bview(file));
+    let default_hold_time = args.default_hold_time.or(config.default_hold_time).unwrap_or(DEFAULT_HOLD_TIME);
+    let send_hold_time_multiple = args.send_hold_time_multiple.or(config.send_hold_time_multiple).unwrap_or(DEFAULT_SEND_HOLD_TIME_MULTIPLE);
+    let mut processor = MrtProcessor::new(default_hold_time, Some(send_hold_time_multiple)).with_strict(config.strict);
+    if let Some(max_peers) = args.max_peers {
+        processor = processor.with_max_peers(max_peers);
+    }
+    if let Some(remap_file) = &config.asn_remap_table {
+        processor = processor.with_asn_remap_table(load_remap_table(remap_file)?);
+    }
+    let resume_ts = match &args.resume_from {
+        Some(checkpoint) => processor.load_checkpoint(checkpoint)?,
+        None => {
+            for (i, file) in config.initial_states.iter().flatten().enumerate() {
+                match i {
+                    0 => processor.process_bview(file)?,
+                    _ => processor.process_bview_with_mode(file, config.bview_merge_mode)?,
+                }
+            }
+            if let Some(state_json) = &config.initial_state_json {
+                processor.load_state_json(BufReader::new(File::open(state_json)?))?;
+            }
+            None
+        }
+    };
+
+    let checkpoint_dir = Path::new(config.checkpoint_dir.as_deref().unwrap_or("."));
+    let mut files_processed = 0;
+    for file in &expand_update_files(&config.update_files)? {
+        if let Some(resume_ts) = resume_ts
+            && first_record_timestamp(file)?.is_some_and(|ts| ts < resume_ts) {
+            log::info!("Skipping {}, it is covered by the checkpoint.", file);
+            continue;
+        }
+
+        // Bviews in the list or in a listed directory replace the state, like in `MrtProcessor::process_directory`
+        let report = processor.process_file(file)?;
+        log::info!("{}", report);
+        files_processed += 1;
+
+        if let Some(interval) = config.checkpoint_interval
+            && files_processed % interval == 0 {
+            // Name the checkpoint after the last processed timestamp
+            let ts = report.last_timestamp.map(|ts| ts.format("%Y%m%dT%H%M%SZ").to_string()).unwrap_or_else(|| "empty".to_string());
+            processor.save_checkpoint(checkpoint_dir.join(format!("checkpoint_{files_processed:06}_{ts}.bin")))?;
+        }
+    }
+
+    // Time-relative queries are evaluated at the end of the processed data, not the wall clock
+    if let Some(as_of) = processor.last_processed_timestamp() {
+        let ages = processor.global_age_distribution(as_of);
+        log::info!(
+            "State as of {}: {} prefixes, announcement age median {}s, p95 {}s",
+            as_of, ages.total_prefixes, ages.p50_seconds, ages.p95_seconds
+        );
+        for (prefix, count) in processor.global_oscillation_report(as_of, OSCILLATION_THRESHOLD) {
+            log::info!("Oscillating prefix at {}: {} ({} updates in the last minute)", as_of, format_prefix(&prefix), count);
+        }
+        let stats = processor.processing_stats();
+        if stats.unnormalized_prefix_count > 0 {
+            log::info!("{} prefixes with host bits set were normalized", stats.unnormalized_prefix_count);
+        }
+        for row in processor.peer_flap_report(as_of).iter().take_while(|row| row.flap_count > 0) {
+            log::info!("Flapping peer as of {}: {}", as_of, row);
+        }
+    }
 
-    for file in &config.update_files {
-        processor.process_update_file(file)?;
+    if let Some(output) = &config.output {
+        log::info!("Writing {} output to: {}", output.format, output.path);
+        let mut writer = open_output(output)?;
+        export_state(processor.get_current_state(), output.format, &mut writer)?;
+        writer.flush()?;
     }
 
+    match args.output_format {
+        Some(CliOutputFormat::Table) => println!("{}", peer_summary_table(processor.peer_summary_table(), args.sort_by)),
+        Some(CliOutputFormat::Jsonl) => export_jsonl(processor.get_current_state(), &mut std::io::stdout().lock())?,
+        None => {},
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_initial_states() {
+        let config: Config = serde_yaml::from_str("initial_state: a.gz\nupdate_files: []").unwrap();
+        assert_eq!(config.initial_states, Some(vec!["a.gz".to_string()]));
+        assert_eq!(config.bview_merge_mode, MergeMode::Incremental);
+
+        let config: Config = serde_yaml::from_str("initial_states: [a.gz, b.gz]\nbview_merge_mode: clear\nupdate_files: []").unwrap();
+        assert_eq!(config.initial_states, Some(vec!["a.gz".to_string(), "b.gz".to_string()]));
+        assert_eq!(config.bview_merge_mode, MergeMode::Clear);
+
+        let config: Config = serde_yaml::from_str("update_files: []").unwrap();
+        assert_eq!(config.initial_states, None);
+    }
+
+    #[test]
+    fn test_config_unsupported_output_format() {
+        let config: Config = serde_yaml::from_str("update_files: []\noutput: {format: csv, path: '-'}").unwrap();
+        assert!(config.validate().is_ok());
+
+        let config: Config = serde_yaml::from_str("update_files: []\noutput: {format: parquet, path: state.parquet}").unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("parquet"));
+    }
+
+    #[test]
+    fn test_config_checkpoint_interval() {
+        let config: Config = serde_yaml::from_str("update_files: []\ncheckpoint_interval: 1").unwrap();
+        assert!(config.validate().is_ok());
+
+        let config: Config = serde_yaml::from_str("update_files: []\ncheckpoint_interval: 0").unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("checkpoint_interval"));
+    }
+
+    #[test]
+    fn test_config_from_lookup() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+        };
+
+        let config = Config::from_lookup(env(&[
+            ("MRT_UPDATE_FILES", "a.gz:b.gz"),
+            ("MRT_INITIAL_STATE", "bview.gz"),
+            ("MRT_DEFAULT_HOLD_TIME", "90"),
+        ])).unwrap();
+        assert_eq!(config.update_files, vec!["a.gz".to_string(), "b.gz".to_string()]);
+        assert_eq!(config.initial_states, Some(vec!["bview.gz".to_string()]));
+        assert_eq!(config.default_hold_time, Some(90));
+        assert_eq!(config.send_hold_time_multiple, None);
+
+        let err = Config::from_lookup(env(&[])).unwrap_err();
+        assert!(err.to_string().contains("MRT_UPDATE_FILES"));
+
+        let err = Config::from_lookup(env(&[("MRT_UPDATE_FILES", "a.gz"), ("MRT_SEND_HOLD_TIME_MULTIPLE", "x")])).unwrap_err();
+        assert!(err.to_string().contains("MRT_SEND_HOLD_TIME_MULTIPLE"));
+
+        // Values from the config file take precedence over the environment
+        let mut config: Config = serde_yaml::from_str("update_files: []\ndefault_hold_time: 30").unwrap();
+        config.fill_from_lookup(env(&[("MRT_DEFAULT_HOLD_TIME", "90"), ("MRT_SEND_HOLD_TIME_MULTIPLE", "2")])).unwrap();
+        assert_eq!(config.default_hold_time, Some(30));
+        assert_eq!(config.send_hold_time_multiple, Some(2));
+    }
+}
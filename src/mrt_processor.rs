@@ -1,11 +1,24 @@
-use bgpkit_parser::BgpkitParser;
+use core::fmt;
+use bgpkit_parser::{BgpkitParser, Elementor, ParserError};
+use bgpkit_parser::models::{AsPath, Asn, BgpElem, BgpUpdateMessage, MrtMessage, MrtRecord, NetworkPrefix, TableDumpV2Message};
+use bytes::Bytes;
+use rayon::prelude::*;
 use chrono::{DateTime, Utc};
-use std::{collections::HashMap, net::IpAddr, path::Path};
-use crate::bgp_state::{BgpKitStateExt, BgpState, ConnectionState};
-use crate::util::{mrt_record_ts, DateTimeExt};
+use serde::{Deserialize, Serialize};
+use std::{collections::{HashMap, HashSet}, hash::{BuildHasher, RandomState}, io::{Read, Write}, net::IpAddr, path::Path, sync::OnceLock, time::{Duration, Instant}};
+use crate::analysis::{infer_community_policy, CommunityPolicySummary};
+use crate::bgp_state::{AgeDistribution, Announcement, BgpKitStateExt, BgpState, ConnectionState, RouteDistinguisher, DEFAULT_MAX_SESSION_HISTORY};
+use crate::checkpoint::{Checkpoint, CHECKPOINT_VERSION};
+use crate::error::ProcessorError;
+use crate::mrt_reader::{FeedResult, MrtFileReader, RawRecordIter};
+use crate::mrt_records::{decode_vpn_update, set_mp_reach_next_hop, AsRemapTable, VpnUpdate};
+use crate::topology::{betweenness_centrality, build_as_adjacency_graph, BETWEENNESS_SAMPLE_SIZE};
+use crate::util::{detect_mrt_file_type, format_duration, format_prefix, list_mrt_files, mrt_record_ts, normalize_prefix, prefix_sort_key, FileType, validate_mrt_file, DateTimeExt, MrtFileInfo, ValidationError};
 
-/// Represents an IP prefix (address + prefix length)
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Represents a BGP peer (address + ASN).
+///
+/// Peers are ordered by address (IPv4 before IPv6, then numerically) and then by ASN.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct BgpPeer {
     /// IP address of the peer
     pub address: IpAddr,
@@ -23,67 +36,517 @@ impl BgpPeer {
     }
 }
 
-/// Processor for MRT (Multi-threaded Routing Toolkit) files
-pub struct MrtProcessor {
-    current_state: HashMap<BgpPeer, BgpState>,
+/// The peers and their state, ordered by peer
+pub fn sorted_peers<S>(state: &HashMap<BgpPeer, BgpState<S>, S>) -> Vec<(&BgpPeer, &BgpState<S>)> {
+    let mut peers: Vec<(&BgpPeer, &BgpState<S>)> = state.iter().collect();
+    peers.sort_by_key(|(peer, _)| *peer);
+    peers
+}
+
+/// How a bview is combined with the existing state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    /// Discard the existing state before loading the bview
+    Clear,
+    /// Keep the existing state, only add peers and prefixes that are missing
+    Incremental,
+}
+
+/// Summary of a single peer, one row of the peer summary table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerSummaryRow {
+    pub peer_ip: IpAddr,
+    pub peer_asn: u32,
+    pub state: ConnectionState,
+    pub prefixes_v4: usize,
+    pub prefixes_v6: usize,
+    pub hold_time: Option<u16>,
+    pub last_message_timestamp: Option<DateTime<Utc>>,
+}
+
+/// Session stability of a single peer, see `MrtProcessor::peer_flap_report`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerFlapReport {
+    pub peer: BgpPeer,
+    /// Number of sessions that went down in the recorded session history
+    pub flap_count: u32,
+    /// Time spent down after the recorded flaps
+    pub total_downtime: chrono::Duration,
+    pub last_flap_ts: Option<DateTime<Utc>>,
+    pub current_state: ConnectionState,
+    /// Time since the session was established, `None` when it is not established
+    pub uptime: Option<chrono::Duration>,
+}
+
+/// What changed while processing a single update file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileProcessingReport {
+    pub file_path: String,
+    /// Number of MRT records in the file
+    pub records_processed: u64,
+    /// Number of streamed records that failed to parse and were skipped, see `MrtProcessor::feed_bytes`
+    pub records_skipped: u64,
+    /// Number of announced prefixes
+    pub announcements: u64,
+    /// Number of withdrawn prefixes
+    pub withdrawals: u64,
+    /// Number of withdrawals for prefixes that were not announced
+    pub orphan_withdrawals: u64,
+    /// Number of connection state changes (OPEN, NOTIFICATION and state change records)
+    pub state_changes: u64,
+    /// Number of peers moved to idle because their hold timer expired
+    pub hold_timer_expirations: u64,
+    /// Wall clock time spent processing the file
+    pub duration: Duration,
+    /// Peers that sent at least one record
+    pub peers_affected: HashSet<BgpPeer>,
+    /// Timestamp of the last record in the file
+    pub last_timestamp: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for FileProcessingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} records, {} announcements, {} withdrawals, {} state changes, {} hold timer expirations, {} peers affected in {:?}",
+            self.file_path, self.records_processed, self.announcements, self.withdrawals, self.state_changes,
+            self.hold_timer_expirations, self.peers_affected.len(), self.duration
+        )
+    }
+}
+
+impl fmt::Display for PeerFlapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (AS{}): {} flaps, down for {}", self.peer.address, self.peer.peer_as, self.flap_count, format_duration(self.total_downtime))?;
+        if let Some(last_flap_ts) = self.last_flap_ts {
+            write!(f, ", last flap at {}", last_flap_ts)?;
+        }
+        match self.uptime {
+            Some(uptime) => write!(f, ", up for {}", format_duration(uptime)),
+            None => write!(f, ", {}", self.current_state),
+        }
+    }
+}
+
+/// Counters over all bviews and update files read by the processor, unlike `FileProcessingReport` not reset per file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessingStats {
+    /// Announced or withdrawn prefixes with host bits set that were normalized before use
+    pub unnormalized_prefix_count: u64,
+}
+
+/// A prefix that a bview announced more than once for the same peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BviewInconsistency {
+    pub peer: BgpPeer,
+    pub prefix: NetworkPrefix,
+    /// Number of announcements of the prefix in the bview
+    pub count: usize,
+    /// The AS paths of the announcements in the order they were seen, without duplicates
+    pub distinct_as_paths: Vec<String>,
+}
+
+/// File name pattern of `process_directory` when none is given
+pub const DEFAULT_DIRECTORY_PATTERN: &str = "*.gz";
+
+/// Source name used in logs and reports for data read from a parser instead of a file
+const PARSER_SOURCE: &str = "<parser>";
+
+/// Source name used in logs and reports for records passed to `process_records`
+const RECORDS_SOURCE: &str = "<records>";
+
+/// Processor for MRT (Multi-threaded Routing Toolkit) files.
+///
+/// `S` is the hasher of the peer and prefix maps. The default `RandomState` resists hash flooding, `with_ahash`
+/// trades that for faster hashing of the prefix keys of full table dumps, see `benches/hasher.rs`.
+#[derive(Clone)]
+pub struct MrtProcessor<S = RandomState> {
+    current_state: HashMap<BgpPeer, BgpState<S>, S>,
     send_hold_time_multiple: Option<u16>,
     default_hold_time: u16,
+    /// Cached result of `transit_as_ranking`
+    topology_cache: OnceLock<Vec<(u32, f64)>>,
+    /// Maximum timestamp of all processed records, of bviews and update files
+    last_processed_timestamp: Option<DateTime<Utc>>,
+    /// Announcements plus withdrawals per prefix over all peers and update files
+    churn_counters: HashMap<NetworkPrefix, u64, S>,
+    /// Maximum number of peers in the state, records of further peers are skipped
+    max_peers: Option<usize>,
+    /// Capacity of the session history of new peers
+    max_session_history: usize,
+    /// ASN remapping applied to the AS path of announcements before they are stored
+    asn_remap_table: Option<AsRemapTable>,
+    /// Log data errors such as prefixes with host bits set at warn instead of debug level
+    strict: bool,
+    processing_stats: ProcessingStats,
+    /// Prefixes announced more than once per peer by the bviews since the last `MergeMode::Clear` load
+    bview_inconsistencies: HashMap<(BgpPeer, NetworkPrefix), BviewInconsistency, S>,
+    /// Buffered partial record of `feed_bytes`
+    stream_reader: MrtFileReader,
+}
+
+/// State of a peer, inserting it when it is new and the peer limit allows it
+fn peer_entry<S: BuildHasher + Default>(
+    state: &mut HashMap<BgpPeer, BgpState<S>, S>,
+    max_peers: Option<usize>,
+    max_session_history: usize,
+    peer: BgpPeer,
+) -> Option<&mut BgpState<S>> {
+    if max_peers.is_some_and(|max| state.len() >= max) && !state.contains_key(&peer) {
+        log::debug!("Peer limit reached, skipping record of {:?}", peer);
+        return None;
+    }
+    Some(state.entry(peer).or_insert_with(|| BgpState::default().with_max_session_history(max_session_history)))
+}
+
+// Apply the ASN remapping table, if any, to the AS path of the element
+fn remap_asns(table: Option<&AsRemapTable>, elem: &mut BgpElem) {
+    if let (Some(table), Some(path)) = (table, elem.as_path.as_mut()) {
+        table.remap_path(path);
+    }
+}
+
+// Prefix with the host bits zeroed, counting and logging the prefixes that needed it
+fn normalize_checked(stats: &mut ProcessingStats, strict: bool, peer: &BgpPeer, prefix: NetworkPrefix) -> NetworkPrefix {
+    let normalized = normalize_prefix(prefix);
+    if normalized != prefix {
+        stats.unnormalized_prefix_count += 1;
+        if strict {
+            log::warn!("[{}/{}] Prefix {} has host bits set, using {}", peer.address, peer.peer_as, format_prefix(&prefix), format_prefix(&normalized));
+        }
+    }
+    normalized
+}
+
+// Announcements of the VPN routes with the attributes of the update, `Elementor` only builds them for unicast prefixes
+fn vpn_elems(update: &BgpUpdateMessage, vpn_update: &VpnUpdate, ts: f64, peer_ip: &IpAddr, peer_asn: &Asn) -> Vec<(RouteDistinguisher, BgpElem)> {
+    if vpn_update.announced.is_empty() {
+        return Vec::new();
+    }
+    let template = BgpUpdateMessage {
+        withdrawn_prefixes: Vec::new(),
+        attributes: update.attributes.clone(),
+        announced_prefixes: vpn_update.announced.iter().map(|(_, prefix)| *prefix).collect(),
+    };
+    // The elements of `announced_prefixes` come first and in order
+    let elems = Elementor::bgp_update_to_elems(template, ts, peer_ip, peer_asn);
+    vpn_update.announced.iter().zip(elems).map(|((rd, _), mut elem)| {
+        elem.next_hop = vpn_update.next_hop;
+        (*rd, elem)
+    }).collect()
+}
+
+// Count a repeated announcement of a prefix for the same peer within a bview
+fn record_bview_duplicate<S: BuildHasher>(
+    inconsistencies: &mut HashMap<(BgpPeer, NetworkPrefix), BviewInconsistency, S>,
+    peer: BgpPeer,
+    prefix: NetworkPrefix,
+    existing_path: &Option<AsPath>,
+    new_path: &Option<AsPath>,
+) {
+    let format_path = |path: &Option<AsPath>| path.as_ref().map(|p| p.to_string()).unwrap_or_default();
+    let inconsistency = inconsistencies.entry((peer.clone(), prefix)).or_insert_with(|| BviewInconsistency {
+        peer,
+        prefix,
+        count: 1,
+        distinct_as_paths: vec![format_path(existing_path)],
+    });
+    inconsistency.count += 1;
+    let path = format_path(new_path);
+    if !inconsistency.distinct_as_paths.contains(&path) {
+        inconsistency.distinct_as_paths.push(path);
+    }
+}
+
+impl Default for MrtProcessor {
+    /// Processor with a default hold time of 180 seconds and without send hold timer
+    fn default() -> Self {
+        MrtProcessor::new(180, None)
+    }
 }
 
 impl MrtProcessor {
     /// Create a new MRT processor
     pub fn new(default_hold_time: u16, send_hold_time_multiple: Option<u16>) -> Self {
+        Self::with_hasher(default_hold_time, send_hold_time_multiple)
+    }
+}
+
+impl MrtProcessor<ahash::RandomState> {
+    /// Create a new MRT processor hashing with `ahash`, faster on large bviews but not resistant to hash flooding
+    pub fn with_ahash(default_hold_time: u16, send_hold_time_multiple: Option<u16>) -> Self {
+        Self::with_hasher(default_hold_time, send_hold_time_multiple)
+    }
+}
+
+impl<S: BuildHasher + Default + Clone> MrtProcessor<S> {
+    /// Create a new MRT processor with hasher `S` for the peer and prefix maps
+    pub fn with_hasher(default_hold_time: u16, send_hold_time_multiple: Option<u16>) -> Self {
         MrtProcessor {
-            current_state: HashMap::new(),
+            current_state: HashMap::default(),
             send_hold_time_multiple,
-            default_hold_time
+            default_hold_time,
+            topology_cache: OnceLock::new(),
+            last_processed_timestamp: None,
+            churn_counters: HashMap::default(),
+            max_peers: None,
+            max_session_history: DEFAULT_MAX_SESSION_HISTORY,
+            asn_remap_table: None,
+            strict: false,
+            processing_stats: ProcessingStats::default(),
+            bview_inconsistencies: HashMap::default(),
+            stream_reader: MrtFileReader::new(),
         }
     }
 
-    pub fn default() -> Self {
-        MrtProcessor::new(180, None)
+    /// Only keep the first `max` peers seen, records of other peers are skipped
+    pub fn with_max_peers(mut self, max: usize) -> Self {
+        self.max_peers = Some(max);
+        self
     }
 
-    pub fn process_bview<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(),  Box<dyn std::error::Error>> {
-        let file_str = file_path.as_ref().display().to_string();
-        log::info!("Processing bview: {}", file_str);
+    /// Keep at most `max` connection state changes in the session history of each new peer
+    pub fn with_max_session_history(mut self, max: usize) -> Self {
+        self.max_session_history = max;
+        self
+    }
+
+    /// Remap the ASNs of AS paths that still contain AS_TRANS, for collectors running bgpd without AS4_PATH support
+    pub fn with_asn_remap_table(mut self, table: AsRemapTable) -> Self {
+        self.asn_remap_table = Some(table);
+        self
+    }
+
+    /// Log data errors such as prefixes with host bits set at warn level
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Whether the peer limit is set and reached, i.e. new peers are skipped
+    pub fn peer_limit_reached(&self) -> bool {
+        self.max_peers.is_some_and(|max| self.current_state.len() >= max)
+    }
+
+    /// Independent copy of the processor, e.g. for trying out filters without touching the current state
+    pub fn fork(&self) -> MrtProcessor<S> {
+        self.clone()
+    }
 
-        // Clear the current state
-        self.current_state.clear();
+    /// Load a bview, replacing the current state
+    pub fn process_bview<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(), ProcessorError> {
+        self.process_bview_with_mode(file_path, MergeMode::Clear)
+    }
 
+    /// Load a bview, only adding the peers and prefixes that are not in the current state yet
+    pub fn process_bview_incremental<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(), ProcessorError> {
+        self.process_bview_with_mode(file_path, MergeMode::Incremental)
+    }
+
+    /// Load a bview, merging it into the current state according to `mode`
+    pub fn process_bview_with_mode<P: AsRef<Path>>(&mut self, file_path: P, mode: MergeMode) -> Result<(), ProcessorError> {
+        let file_str = file_path.as_ref().display().to_string();
         let parser = BgpkitParser::new(file_path.as_ref().to_str().unwrap())?;
-        for elem in parser {
-            let peer = BgpPeer::from_elem(&elem);
+        self.load_bview(parser, mode, &file_str)
+    }
 
-            let peer_state = self.current_state.entry(peer).or_insert_with(BgpState::new);
-            match elem.elem_type {
-                bgpkit_parser::models::ElemType::ANNOUNCE => {
-                    peer_state.update_prefix(elem);
-                },
-                bgpkit_parser::models::ElemType::WITHDRAW => {
-                    peer_state.withdraw_prefix(elem.timestamp, elem.prefix);
-                },
+    /// Load a bview from an already constructed parser, replacing the current state.
+    ///
+    /// Filters of the parser select whole records, all entries of a matching record are loaded.
+    pub fn process_bview_from_parser<R: Read>(&mut self, parser: BgpkitParser<R>) -> Result<(), ProcessorError> {
+        self.load_bview(parser, MergeMode::Clear, PARSER_SOURCE)
+    }
+
+    fn load_bview<R: Read>(&mut self, parser: BgpkitParser<R>, mode: MergeMode, file_str: &str) -> Result<(), ProcessorError> {
+        log::info!("Processing bview ({:?}): {}", mode, file_str);
+
+        self.invalidate_topology_cache();
+
+        // Peers that were known before this bview keep their announcements
+        let existing_peers: HashSet<BgpPeer> = match mode {
+            MergeMode::Clear => {
+                self.current_state.clear();
+                self.bview_inconsistencies.clear();
+                HashSet::new()
+            },
+            MergeMode::Incremental => self.current_state.keys().cloned().collect(),
+        };
+        let mut added_prefixes = 0;
+        let mut last_ts: Option<f64> = None;
+
+        let mut elementor = Elementor::new();
+        for record in parser.into_record_iter() {
+            // Peers of the index table are tracked even without RIB entries
+            if let MrtMessage::TableDumpV2Message(TableDumpV2Message::PeerIndexTable(index_table)) = &record.message {
+                for peer in index_table.id_peer_map.values() {
+                    let peer = BgpPeer { address: peer.peer_address, peer_as: peer.peer_asn.to_u32() };
+                    if !existing_peers.contains(&peer) {
+                        peer_entry(&mut self.current_state, self.max_peers, self.max_session_history, peer);
+                    }
+                }
+            }
+
+            for mut elem in elementor.record_to_elems(record) {
+                remap_asns(self.asn_remap_table.as_ref(), &mut elem);
+                let peer = BgpPeer::from_elem(&elem);
+                last_ts = Some(last_ts.map_or(elem.timestamp, |ts| ts.max(elem.timestamp)));
+
+                if existing_peers.contains(&peer) {
+                    if elem.elem_type == bgpkit_parser::models::ElemType::ANNOUNCE {
+                        elem.prefix = normalize_checked(&mut self.processing_stats, self.strict, &peer, elem.prefix);
+                        let peer_state = self.current_state.get_mut(&peer).unwrap();
+                        if peer_state.add_prefix_if_absent(elem) {
+                            added_prefixes += 1;
+                        }
+                    }
+                    continue;
+                }
+
+                let Some(peer_state) = peer_entry(&mut self.current_state, self.max_peers, self.max_session_history, peer.clone()) else {
+                    continue;
+                };
+                let prefix = normalize_checked(&mut self.processing_stats, self.strict, &peer, elem.prefix);
+                elem.prefix = prefix;
+                match elem.elem_type {
+                    bgpkit_parser::models::ElemType::ANNOUNCE => {
+                        if let Some(existing) = peer_state.announcements().get(&prefix) {
+                            record_bview_duplicate(&mut self.bview_inconsistencies, peer, prefix, &existing.as_path, &elem.as_path);
+                        }
+                        peer_state.update_prefix(elem);
+                    },
+                    bgpkit_parser::models::ElemType::WITHDRAW => {
+                        peer_state.withdraw_prefix(elem.timestamp, elem.prefix);
+                    },
+                }
+            }
+        }
+
+        // The table dump is a snapshot, its entries are not update bursts
+        for (peer, state) in self.current_state.iter_mut() {
+            if !existing_peers.contains(peer) {
+                state.clear_bursts();
             }
         }
 
+        let last_ts = last_ts.and_then(|ts| DateTime::from_timestamp(ts as i64, (ts.fract() * 1_000_000_000.0) as u32));
+        self.last_processed_timestamp = self.last_processed_timestamp.max(last_ts);
+
+        if !self.bview_inconsistencies.is_empty() {
+            log::warn!("{} prefixes were announced more than once for the same peer, see validate_bview_consistency", self.bview_inconsistencies.len());
+        }
+        log::info!(
+            "Loaded bview {}: {} peers contributed, {} prefixes added to existing peers.",
+            file_str, self.current_state.len() - existing_peers.len(), added_prefixes
+        );
+
         Ok(())
     }
 
-    /// Process an MRT file at the given path
-    pub fn process_update_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(),  Box<dyn std::error::Error>> {
+    /// Process all files in `dir` matching `pattern` (default `*.gz`) in lexicographic order.
+    ///
+    /// Table dumps replace the state like `process_bview`, all other files are processed as update files. A file that
+    /// fails is recorded in the results and does not stop the run.
+    pub fn process_directory<P: AsRef<Path>>(&mut self, dir: P, pattern: Option<&str>) -> Result<Vec<Result<FileProcessingReport, ProcessorError>>, ProcessorError> {
+        let files = list_mrt_files(&dir, pattern.unwrap_or(DEFAULT_DIRECTORY_PATTERN))?;
+        log::info!("Processing {} files from {}", files.len(), dir.as_ref().display());
+
+        Ok(files.iter().map(|file| {
+            let result = self.process_file(file);
+            if let Err(e) = &result {
+                log::error!("Failed to process {}: {}", file.display(), e);
+            }
+            result
+        }).collect())
+    }
+
+    /// Process an MRT file of any type, detected with `detect_mrt_file_type`.
+    ///
+    /// A table dump replaces the state like `process_bview`, its report lists all peers of the new state. Other files
+    /// are processed as update files.
+    pub fn process_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<FileProcessingReport, ProcessorError> {
+        match detect_mrt_file_type(&file_path)? {
+            FileType::TableDump => {
+                let start = Instant::now();
+                self.process_bview(&file_path)?;
+                Ok(FileProcessingReport {
+                    file_path: file_path.as_ref().display().to_string(),
+                    peers_affected: self.current_state.keys().cloned().collect(),
+                    duration: start.elapsed(),
+                    ..Default::default()
+                })
+            },
+            FileType::Updates | FileType::Mixed => self.process_update_file(file_path),
+        }
+    }
+
+    /// Process an MRT file at the given path.
+    ///
+    /// Also reads the VPN routes (SAFI 128 and 129) that `bgpkit_parser` drops from the raw records, see
+    /// `vpn_routes_for_rd`. The `*_from_parser` and record based entry points only see the parsed records.
+    pub fn process_update_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<FileProcessingReport, ProcessorError> {
         let file_str = file_path.as_ref().display().to_string();
+        let reader = oneio::get_reader(file_path.as_ref().to_str().unwrap()).map_err(ParserError::from)?;
         log::info!("Processing update file: {}", file_str);
+        let records = RawRecordIter::new(reader).map(|(record, raw)| (record, Some(raw)));
+        let report = self.process_updates(records, file_str)?;
+        log::info!("Finished processing file: {}", report.file_path);
+        Ok(report)
+    }
 
-        // Create a parser for the MRT file
-        let parser = BgpkitParser::new(file_path.as_ref().to_str().unwrap())?;
+    /// Process the updates from an already constructed parser, e.g. one reading from memory or stdin
+    pub fn process_update_file_from_parser<R: Read>(&mut self, parser: BgpkitParser<R>) -> Result<FileProcessingReport, ProcessorError> {
+        log::info!("Processing update file: {}", PARSER_SOURCE);
+        let report = self.process_updates(parser.into_record_iter().map(|record| (record, None)), PARSER_SOURCE.to_string())?;
+        log::info!("Finished processing file: {}", report.file_path);
+        Ok(report)
+    }
+
+    /// Process already parsed update records, e.g. from `MrtFileReader` fed by an async stream.
+    ///
+    /// Like an update file, the records must be BGP4MP messages and state changes. An empty batch leaves the processor
+    /// untouched, including the cached transit AS ranking.
+    pub fn process_records(&mut self, records: Vec<MrtRecord>) -> Result<FileProcessingReport, ProcessorError> {
+        if records.is_empty() {
+            return Ok(FileProcessingReport {
+                file_path: RECORDS_SOURCE.to_string(),
+                ..Default::default()
+            });
+        }
+        log::debug!("Processing {} records", records.len());
+        self.process_updates(records.into_iter().map(|record| (record, None)), RECORDS_SOURCE.to_string())
+    }
+
+    /// Process the next chunk of an update stream, a record split over chunks is processed once it is complete.
+    ///
+    /// Records that fail to parse are logged and counted in `FileProcessingReport::records_skipped`.
+    pub fn feed_bytes(&mut self, data: &[u8]) -> Result<FileProcessingReport, ProcessorError> {
+        let FeedResult { records, errors } = self.stream_reader.feed(data);
+        for e in &errors {
+            log::warn!("Skipping MRT record from stream: {}", e);
+        }
+        let mut report = self.process_records(records)?;
+        report.records_skipped = errors.len() as u64;
+        Ok(report)
+    }
+
+    // Process the records, each with its raw bytes when it was read from a file for the VPN routes
+    fn process_updates<I: IntoIterator<Item = (MrtRecord, Option<Bytes>)>>(&mut self, records: I, file_str: String) -> Result<FileProcessingReport, ProcessorError> {
+        self.invalidate_topology_cache();
+        let start = Instant::now();
+        let mut report = FileProcessingReport {
+            file_path: file_str.clone(),
+            ..Default::default()
+        };
 
         // Last timestamp seen over all peers
         let mut last_ts: Option<DateTime<Utc>> = None;
 
         // Iterate over BGP messages in the file
-        for record in parser.into_record_iter() {
+        for (record, raw) in records {
             let ts = mrt_record_ts(&record);
-            last_ts = last_ts.map(|old| old.max(ts)).or_else(|| Some(ts));
+            last_ts = last_ts.map(|old| old.max(ts)).or(Some(ts));
+            report.records_processed += 1;
 
             match record.message {
                 bgpkit_parser::models::MrtMessage::Bgp4Mp(msg) => {
@@ -93,7 +556,10 @@ impl MrtProcessor {
                                 address: msg.peer_ip,
                                 peer_as: msg.peer_asn.to_u32(),
                             };
-                            let peer_state = self.current_state.entry(peer).or_insert_with(BgpState::new);
+                            let Some(peer_state) = peer_entry(&mut self.current_state, self.max_peers, self.max_session_history, peer.clone()) else {
+                                continue;
+                            };
+                            report.peers_affected.insert(peer.clone());
 
                             match msg.bgp_message {
                                 bgpkit_parser::models::BgpMessage::Open(bgp_open_message) => {
@@ -102,22 +568,59 @@ impl MrtProcessor {
                                         log::info!("[{}/{}] OPEN: {:?}", msg.peer_ip, msg.peer_asn, bgp_open_message.opt_params);
                                     }
                                     peer_state.open_message(ts, bgp_open_message);
+                                    report.state_changes += 1;
                                 },
                                 bgpkit_parser::models::BgpMessage::Update(bgp_update_message) => {
                                     // Construct the BgpElems from the BgpUpdateMessage
                                     // TODO: Construct only the updates, use the withdraws based on the information already available.
-                                    let elements = bgpkit_parser::Elementor::bgp_update_to_elems(bgp_update_message, ts.to_timestamp_f64(), &msg.peer_ip, &msg.peer_asn);
+                                    let vpn_update = match raw.as_deref().map(decode_vpn_update) {
+                                        Some(Ok(vpn_update)) => vpn_update,
+                                        Some(Err(e)) => {
+                                            log::warn!("[{}/{}] Skipping VPN routes of update: {}", msg.peer_ip, msg.peer_asn, e);
+                                            VpnUpdate::default()
+                                        },
+                                        None => VpnUpdate::default(),
+                                    };
+                                    let vpn_elements = vpn_elems(&bgp_update_message, &vpn_update, ts.to_timestamp_f64(), &msg.peer_ip, &msg.peer_asn);
 
-                                    for elem in elements {
+                                    let mp_reach = bgp_update_message.attributes.get_reachable_nlri().cloned();
+                                    let mut elements = bgpkit_parser::Elementor::bgp_update_to_elems(bgp_update_message, ts.to_timestamp_f64(), &msg.peer_ip, &msg.peer_asn);
+                                    if let Some(nlri) = &mp_reach {
+                                        set_mp_reach_next_hop(&mut elements, nlri);
+                                    }
+
+                                    for mut elem in elements {
+                                        remap_asns(self.asn_remap_table.as_ref(), &mut elem);
+                                        let prefix = normalize_checked(&mut self.processing_stats, self.strict, &BgpPeer::from_elem(&elem), elem.prefix);
+                                        elem.prefix = prefix;
+                                        *self.churn_counters.entry(prefix).or_insert(0) += 1;
                                         match elem.elem_type {
                                             bgpkit_parser::models::ElemType::ANNOUNCE => {
                                                 peer_state.update_prefix(elem);
+                                                report.announcements += 1;
                                             },
                                             bgpkit_parser::models::ElemType::WITHDRAW => {
-                                                peer_state.withdraw_prefix(elem.timestamp, elem.prefix);
+                                                if !peer_state.withdraw_prefix(elem.timestamp, elem.prefix) {
+                                                    report.orphan_withdrawals += 1;
+                                                }
+                                                report.withdrawals += 1;
                                             },
                                         }
                                     }
+
+                                    for (rd, mut elem) in vpn_elements {
+                                        remap_asns(self.asn_remap_table.as_ref(), &mut elem);
+                                        elem.prefix = normalize_checked(&mut self.processing_stats, self.strict, &peer, elem.prefix);
+                                        peer_state.update_vpn_prefix(rd, elem);
+                                        report.announcements += 1;
+                                    }
+                                    for (rd, prefix) in vpn_update.withdrawn {
+                                        let prefix = normalize_checked(&mut self.processing_stats, self.strict, &peer, prefix);
+                                        if !peer_state.withdraw_vpn_prefix(ts.to_timestamp_f64(), rd, prefix) {
+                                            report.orphan_withdrawals += 1;
+                                        }
+                                        report.withdrawals += 1;
+                                    }
                                 },
                                 bgpkit_parser::models::BgpMessage::KeepAlive => {
                                     // Update last timestamp for peer
@@ -126,7 +629,11 @@ impl MrtProcessor {
                                 bgpkit_parser::models::BgpMessage::Notification(bgp_notification_message) => {
                                     log::debug!("{}: Received notification message from peer: {:?}", ts, bgp_notification_message);
                                     // Move state to idle.
-                                    peer_state.update_connection_state(ts, ConnectionState::Idle);
+                                    let notification = peer_state.notification_message(ts, &bgp_notification_message);
+                                    report.state_changes += 1;
+                                    if let Some(shutdown_message) = &notification.shutdown_message {
+                                        log::info!("[{}/{}] NOTIFICATION: shutdown communication: {:?}", msg.peer_ip, msg.peer_asn, shutdown_message);
+                                    }
                                 }
                             }
                         },
@@ -135,14 +642,21 @@ impl MrtProcessor {
                                 address: msg.peer_addr,
                                 peer_as: msg.peer_asn.to_u32(),
                             };
-                            let peer_state = self.current_state.entry(peer).or_insert_with(BgpState::new);
+                            let Some(peer_state) = peer_entry(&mut self.current_state, self.max_peers, self.max_session_history, peer.clone()) else {
+                                continue;
+                            };
+                            report.peers_affected.insert(peer);
                             peer_state.update_connection_state(ts, msg.new_state.to_connection_state());
+                            report.state_changes += 1;
                         },
 
                     }
                 },
                 _ => {
-                    return Err(format!("Unsupported content: Update file {file_str} might be a bview.").into());
+                    return Err(ProcessorError::UnsupportedFileType {
+                        path: file_str,
+                        reason: "Unsupported content: update file might be a bview.".to_string(),
+                    });
                 }
             }
         }
@@ -150,12 +664,12 @@ impl MrtProcessor {
         // Check peers for validity
         if let Some(last_ts) = last_ts {
             for (peer, state) in self.current_state.iter_mut() {
-                if state.connection_state == ConnectionState::Idle {
+                if *state.connection_state() == ConnectionState::Idle {
                     continue;
                 }
                 // Only for peers that have last messages
-                if let Some(last_message_ts) = state.last_message_timestamp {
-                    let hold_time = match state.hold_time {
+                if let Some(last_message_ts) = state.last_message_timestamp() {
+                    let hold_time = match state.hold_time() {
                         Some(hold_time) => hold_time,
                         None => {
                             log::warn!("{}: Peer {:?} does not have a hold time - no open message.", last_ts, peer);
@@ -163,23 +677,993 @@ impl MrtProcessor {
                         }
                     };
 
-                    let effective_hold_time = self.send_hold_time_multiple.unwrap_or(1) * hold_time;
-                    let cutoff = last_ts + chrono::Duration::seconds(effective_hold_time as i64);
+                    let effective_hold_time = chrono::Duration::seconds(self.send_hold_time_multiple.unwrap_or(1) as i64 * hold_time as i64);
 
-                    if last_message_ts < cutoff {
-                        log::info!("Hold timer expired for {:?}, last message at {} (cutoff: {}), resetting state to idle.", peer, last_message_ts, cutoff);
+                    if state.hold_timer_expired(last_ts, effective_hold_time) {
+                        log::info!(
+                            "Hold timer expired for {:?}, last message at {} ({} before end of file, hold time: {}), resetting state to idle.",
+                            peer, last_message_ts, format_duration(last_ts - last_message_ts), format_duration(effective_hold_time)
+                        );
                         state.update_connection_state(last_ts, ConnectionState::Idle);
+                        report.hold_timer_expirations += 1;
                     }
                 }
             }
         }
 
-        log::info!("Finished processing file: {}", file_path.as_ref().display());
+        self.last_processed_timestamp = self.last_processed_timestamp.max(last_ts);
+
+        if report.orphan_withdrawals * 10 > report.withdrawals {
+            log::warn!(
+                "{}: {} of {} withdrawals were for prefixes that were not announced, consider loading a bview first.",
+                file_str, report.orphan_withdrawals, report.withdrawals
+            );
+        }
+
+        report.last_timestamp = last_ts;
+        report.duration = start.elapsed();
+        Ok(report)
+    }
+
+    /// Prefixes with more than `threshold` announcements and withdrawals in the 60 seconds before `ts`, summed over
+    /// all peers
+    pub fn global_oscillation_report(&self, ts: DateTime<Utc>, threshold: u32) -> Vec<(NetworkPrefix, u32)> {
+        let mut counts: HashMap<NetworkPrefix, u32> = HashMap::new();
+        for state in self.current_state.values() {
+            for (prefix, count) in state.burst_counts(ts) {
+                *counts.entry(prefix).or_insert(0) += count;
+            }
+        }
+
+        let mut result: Vec<(NetworkPrefix, u32)> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > threshold)
+            .collect();
+        result.sort_by_key(|(prefix, count)| (std::cmp::Reverse(*count), prefix_sort_key(prefix), prefix.path_id));
+        result
+    }
+
+    /// The prefixes whose AS path contains `asn` per established peer, peers without such a prefix are left out
+    pub fn global_prefixes_transiting_as(&self, asn: u32) -> HashMap<BgpPeer, Vec<NetworkPrefix>> {
+        self.current_state
+            .iter()
+            .filter(|(_, state)| *state.connection_state() == ConnectionState::Established)
+            .filter_map(|(peer, state)| {
+                let prefixes: Vec<NetworkPrefix> = state.prefixes_transiting_as(asn).into_iter().map(|(prefix, _)| *prefix).collect();
+                (!prefixes.is_empty()).then(|| (peer.clone(), prefixes))
+            })
+            .collect()
+    }
+
+    /// The VPN routes with route distinguisher `rd` per peer ordered by prefix, peers without such a route are left out
+    pub fn vpn_routes_for_rd(&self, rd: &RouteDistinguisher) -> HashMap<BgpPeer, Vec<(NetworkPrefix, &Announcement)>> {
+        self.current_state
+            .iter()
+            .filter_map(|(peer, state)| {
+                let mut routes: Vec<(NetworkPrefix, &Announcement)> = state.vpn_announcements()
+                    .iter()
+                    .filter(|((route_rd, _), _)| route_rd == rd)
+                    .map(|((_, prefix), ann)| (*prefix, ann))
+                    .collect();
+                routes.sort_by_key(|(prefix, _)| (prefix_sort_key(prefix), prefix.path_id));
+                (!routes.is_empty()).then(|| (peer.clone(), routes))
+            })
+            .collect()
+    }
+
+    /// Peers holding less than `threshold` of their peak number of prefixes, ordered by peer
+    pub fn low_reachability_peers(&self, threshold: f64) -> Vec<(&BgpPeer, f64)> {
+        sorted_peers(&self.current_state)
+            .into_iter()
+            .map(|(peer, state)| (peer, state.reachable_prefix_ratio()))
+            .filter(|(_, ratio)| *ratio < threshold)
+            .collect()
+    }
+
+    /// Prefixes announced more than once for the same peer by the loaded bviews, ordered by peer and prefix.
+    ///
+    /// Only bview entries of peers new to the state are checked, `MergeMode::Incremental` does not count entries for
+    /// peers that were already known.
+    pub fn validate_bview_consistency(&self) -> Vec<BviewInconsistency> {
+        let mut result: Vec<BviewInconsistency> = self.bview_inconsistencies.values().cloned().collect();
+        result.sort_by(|a, b| a.peer.cmp(&b.peer).then((prefix_sort_key(&a.prefix), a.prefix.path_id).cmp(&(prefix_sort_key(&b.prefix), b.prefix.path_id))));
+        result
+    }
+
+    /// Estimated convergence time of every peer after its session was last established, see
+    /// `BgpState::estimated_convergence_time`
+    pub fn convergence_summary(&self) -> HashMap<BgpPeer, Option<Duration>> {
+        self.current_state
+            .iter()
+            .map(|(peer, state)| (peer.clone(), state.estimated_convergence_time()))
+            .collect()
+    }
+
+    /// Communities and likely operator of the announcements of every peer, see `infer_community_policy`
+    pub fn community_policy_by_peer(&self) -> HashMap<BgpPeer, CommunityPolicySummary> {
+        self.current_state
+            .iter()
+            .map(|(peer, state)| (peer.clone(), infer_community_policy(state.announcements())))
+            .collect()
+    }
+
+    /// Flaps, downtime and uptime at `as_of` of every peer, most flaps first and then ordered by peer
+    pub fn peer_flap_report(&self, as_of: DateTime<Utc>) -> Vec<PeerFlapReport> {
+        let mut report: Vec<PeerFlapReport> = sorted_peers(&self.current_state).into_iter().map(|(peer, state)| {
+            let history = state.session_history();
+            PeerFlapReport {
+                peer: peer.clone(),
+                flap_count: history.flap_count(),
+                total_downtime: history.total_downtime(as_of),
+                last_flap_ts: history.last_flap(),
+                current_state: state.connection_state().clone(),
+                uptime: state.established_since().map(|since| as_of - since),
+            }
+        }).collect();
+        report.sort_by_key(|row| std::cmp::Reverse(row.flap_count));
+        report
+    }
+
+    /// All tracked peers regardless of their state, ordered by peer
+    pub fn all_peers(&self) -> Vec<&BgpPeer> {
+        sorted_peers(&self.current_state).into_iter().map(|(peer, _)| peer).collect()
+    }
+
+    /// The peers in the given connection state, ordered by peer
+    pub fn peers_in_state(&self, state: ConnectionState) -> Vec<&BgpPeer> {
+        sorted_peers(&self.current_state)
+            .into_iter()
+            .filter(|(_, peer_state)| *peer_state.connection_state() == state)
+            .map(|(peer, _)| peer)
+            .collect()
+    }
+
+    /// The peers with an established session, ordered by peer
+    pub fn established_peers(&self) -> Vec<&BgpPeer> {
+        self.peers_in_state(ConnectionState::Established)
+    }
+
+    /// One summary row per peer, ordered by peer
+    pub fn peer_summary_table(&self) -> Vec<PeerSummaryRow> {
+        sorted_peers(&self.current_state).into_iter().map(|(peer, state)| {
+            let (prefixes_v4, prefixes_v6) = state.prefix_counts();
+            PeerSummaryRow {
+                peer_ip: peer.address,
+                peer_asn: peer.peer_as,
+                state: state.connection_state().clone(),
+                prefixes_v4,
+                prefixes_v6,
+                hold_time: state.hold_time(),
+                last_message_timestamp: state.last_message_timestamp(),
+            }
+        }).collect()
+    }
+
+    /// All shutdown communications received, ordered by peer and then by time
+    pub fn shutdown_messages(&self) -> Vec<(BgpPeer, String)> {
+        sorted_peers(&self.current_state)
+            .into_iter()
+            .flat_map(|(peer, state)| {
+                state.notifications()
+                    .iter()
+                    .filter_map(move |notification| notification.shutdown_message.clone().map(|message| (peer.clone(), message)))
+            })
+            .collect()
+    }
+
+    /// ASes ranked by (approximate) betweenness centrality in the AS graph of all established peers, most central
+    /// first.
+    ///
+    /// The ranking is cached until the state changes or `invalidate_topology_cache` is called.
+    pub fn transit_as_ranking(&self) -> Vec<(u32, f64)> {
+        self.topology_cache
+            .get_or_init(|| betweenness_centrality(&build_as_adjacency_graph(&self.current_state), BETWEENNESS_SAMPLE_SIZE))
+            .clone()
+    }
+
+    /// Drop the cached transit AS ranking
+    pub fn invalidate_topology_cache(&mut self) {
+        self.topology_cache = OnceLock::new();
+    }
+
+    /// The `n` prefixes with the most announcements and withdrawals over all peers, most churn first.
+    ///
+    /// Counts accumulate over all update files, bviews do not count as churn.
+    pub fn top_n_prefixes_by_churn(&self, n: usize) -> Vec<(NetworkPrefix, u64)> {
+        let mut churn: Vec<(NetworkPrefix, u64)> = self.churn_counters.iter().map(|(prefix, count)| (*prefix, *count)).collect();
+        churn.sort_by_key(|(prefix, count)| (std::cmp::Reverse(*count), prefix_sort_key(prefix), prefix.path_id));
+        churn.truncate(n);
+        churn
+    }
+
+    /// Number of withdrawals for prefixes that were not announced, summed over all peers
+    pub fn total_orphan_withdrawals(&self) -> u64 {
+        self.current_state.values().map(BgpState::orphan_withdrawals).sum()
+    }
+
+    /// Reset the churn counters of `top_n_prefixes_by_churn`
+    pub fn reset_churn_counters(&mut self) {
+        self.churn_counters.clear();
+    }
+
+    /// Time range covered by the data of all peers: the earliest first seen and the latest last seen timestamp
+    pub fn data_coverage(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let first = self.current_state.values().filter_map(BgpState::first_seen).min()?;
+        let last = self.current_state.values().filter_map(BgpState::last_seen).max()?;
+        Some((first, last))
+    }
+
+    /// Distribution of the age of all announcements of established peers at `as_of`
+    pub fn global_age_distribution(&self, as_of: DateTime<Utc>) -> AgeDistribution {
+        let ages = self.current_state
+            .values()
+            .filter(|state| *state.connection_state() == ConnectionState::Established)
+            .flat_map(|state| state.announcement_ages(as_of))
+            .collect();
+        AgeDistribution::from_ages(ages)
+    }
+
+    /// Validate the given MRT files in parallel, results are in the same order as `files`
+    pub fn validate_files(&self, files: &[String]) -> Vec<Result<MrtFileInfo, ValidationError>> {
+        files.par_iter().map(validate_mrt_file).collect()
+    }
+
+    /// Maximum timestamp of all records processed so far, the reference time for queries on the current state
+    pub fn last_processed_timestamp(&self) -> Option<DateTime<Utc>> {
+        self.last_processed_timestamp
+    }
+
+    /// Write the current state to a checkpoint file
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), ProcessorError> {
+        log::info!("Writing checkpoint: {}", path.as_ref().display());
+        let checkpoint = Checkpoint {
+            version: CHECKPOINT_VERSION,
+            last_processed_timestamp: self.last_processed_timestamp,
+            peers: sorted_peers(&self.current_state)
+                .into_iter()
+                .map(|(peer, state)| (peer.clone(), state.clone()))
+                .collect(),
+            churn_counters: self.top_n_prefixes_by_churn(self.churn_counters.len()),
+            bview_inconsistencies: self.validate_bview_consistency(),
+            processing_stats: self.processing_stats.clone(),
+        };
+        checkpoint.write(path)
+    }
+
+    /// Replace the current state and counters with those from a checkpoint file.
+    ///
+    /// Returns the timestamp of the last record processed before the checkpoint was taken.
+    pub fn load_checkpoint<P: AsRef<Path>>(&mut self, path: P) -> Result<Option<DateTime<Utc>>, ProcessorError> {
+        log::info!("Loading checkpoint: {}", path.as_ref().display());
+        let checkpoint = Checkpoint::read(path)?;
+
+        self.invalidate_topology_cache();
+        self.current_state = checkpoint.peers.into_iter().collect();
+        self.last_processed_timestamp = checkpoint.last_processed_timestamp;
+        self.churn_counters = checkpoint.churn_counters.into_iter().collect();
+        self.bview_inconsistencies = checkpoint.bview_inconsistencies
+            .into_iter()
+            .map(|inconsistency| ((inconsistency.peer.clone(), inconsistency.prefix), inconsistency))
+            .collect();
+        self.processing_stats = checkpoint.processing_stats;
+        Ok(checkpoint.last_processed_timestamp)
+    }
+
+    /// Counters over all processed bviews and update files
+    pub fn processing_stats(&self) -> &ProcessingStats {
+        &self.processing_stats
+    }
+
+    /// Write the current state as JSON, a list of `[peer, state]` pairs ordered by peer. Connection states are written
+    /// as their `ConnectionState::to_u8` code.
+    pub fn save_state_json<W: Write>(&self, w: W) -> Result<(), ProcessorError> {
+        serde_json::to_writer(w, &sorted_peers(&self.current_state))
+            .map_err(|e| ProcessorError::InvalidState(format!("Failed to write state: {}", e)))
+    }
+
+    /// Merge state written by `save_state_json`, e.g. by another tool, into the current state.
+    ///
+    /// Peers in the JSON replace peers with the same address and ASN. Unknown connection states are rejected, as is state
+    /// that would exceed the peer limit.
+    pub fn load_state_json<R: Read>(&mut self, r: R) -> Result<(), ProcessorError> {
+        let peers: Vec<(BgpPeer, BgpState<S>)> = serde_json::from_reader(r)
+            .map_err(|e| ProcessorError::InvalidState(format!("Failed to read state JSON: {}", e)))?;
+        log::info!("Loaded state of {} peers from JSON", peers.len());
+
+        if let Some(max_peers) = self.max_peers {
+            let new_peers = peers.iter().filter(|(peer, _)| !self.current_state.contains_key(peer)).count();
+            if self.current_state.len() + new_peers > max_peers {
+                return Err(ProcessorError::PeerLimitExceeded);
+            }
+        }
+
+        self.invalidate_topology_cache();
+        for (peer, mut state) in peers {
+            state.rebuild_community_index();
+            self.current_state.insert(peer, state);
+        }
         Ok(())
     }
 
-    /// Get the current BGP state
-    pub fn get_current_state(&self) -> &HashMap<BgpPeer, BgpState> {
+    /// Get the current BGP state.
+    ///
+    /// Iteration order of the map is arbitrary, use [`sorted_peers`] for a canonical order.
+    pub fn get_current_state(&self) -> &HashMap<BgpPeer, BgpState<S>, S> {
         &self.current_state
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgpkit_parser::encoder::MrtUpdatesEncoder;
+    use crate::bgp_state::AS_TRANS;
+    use bgpkit_parser::models::ElemType;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn elem(ts: f64, peer_ip: &str, peer_asn: u32, elem_type: ElemType, prefix: &str) -> BgpElem {
+        BgpElem {
+            timestamp: ts,
+            elem_type,
+            peer_ip: IpAddr::from_str(peer_ip).unwrap(),
+            peer_asn: peer_asn.into(),
+            prefix: NetworkPrefix::from_str(prefix).unwrap(),
+            next_hop: Some(IpAddr::from_str(peer_ip).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    /// Write the elements as an MRT updates file in the temp directory
+    fn encode_updates(elems: &[BgpElem]) -> Vec<u8> {
+        let mut encoder = MrtUpdatesEncoder::new();
+        for e in elems {
+            encoder.process_elem(e);
+        }
+        encoder.export_bytes().to_vec()
+    }
+
+    fn write_updates_file(name: &str, elems: &[BgpElem]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mrt_state_to_state_{}_{}.mrt", name, std::process::id()));
+        std::fs::write(&path, encode_updates(elems)).unwrap();
+        path
+    }
+
+    fn peer(address: &str, peer_as: u32) -> BgpPeer {
+        BgpPeer { address: IpAddr::from_str(address).unwrap(), peer_as }
+    }
+
+    #[test]
+    fn test_bgp_peer_ordering() {
+        let mut state = HashMap::new();
+        for p in [peer("2001:db8::1", 1), peer("192.0.2.10", 1), peer("192.0.2.2", 2), peer("192.0.2.2", 1)] {
+            state.insert(p, BgpState::new());
+        }
+
+        let sorted: Vec<&BgpPeer> = sorted_peers(&state).into_iter().map(|(p, _)| p).collect();
+        assert_eq!(sorted, vec![&peer("192.0.2.2", 1), &peer("192.0.2.2", 2), &peer("192.0.2.10", 1), &peer("2001:db8::1", 1)]);
+    }
+
+    #[test]
+    fn test_low_reachability_peers() {
+        let ts = DateTime::from_timestamp(1600000000, 0).unwrap();
+        let mut processor = MrtProcessor::default();
+        for e in [
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+            elem(1600000000.0, "192.0.2.2", 65002, ElemType::ANNOUNCE, "198.51.100.0/24"),
+        ] {
+            processor.current_state.entry(BgpPeer::from_elem(&e)).or_default().update_prefix(e);
+        }
+        processor.current_state.get_mut(&peer("192.0.2.1", 65001)).unwrap()
+            .withdraw_prefix(1600000001.0, NetworkPrefix::from_str("198.51.100.0/24").unwrap());
+        processor.current_state.get_mut(&peer("192.0.2.2", 65002)).unwrap().update_connection_state(ts, ConnectionState::Idle);
+
+        assert_eq!(processor.low_reachability_peers(0.5), vec![(&peer("192.0.2.2", 65002), 0.0)]);
+        assert_eq!(processor.low_reachability_peers(0.75).len(), 2);
+    }
+
+    #[test]
+    fn test_peer_flap_report() {
+        let ts = |secs: i64| DateTime::from_timestamp(1600000000 + secs, 0).unwrap();
+        let mut processor = MrtProcessor::default();
+        let stable = processor.current_state.entry(peer("192.0.2.1", 65001)).or_default();
+        stable.update_connection_state(ts(0), ConnectionState::Established);
+        let flapping = processor.current_state.entry(peer("192.0.2.2", 65002)).or_default();
+        for i in 0..2 {
+            flapping.update_connection_state(ts(i * 100), ConnectionState::Established);
+            flapping.update_connection_state(ts(i * 100 + 60), ConnectionState::Active);
+        }
+
+        let report = processor.peer_flap_report(ts(300));
+        assert_eq!(report[0], PeerFlapReport {
+            peer: peer("192.0.2.2", 65002),
+            flap_count: 2,
+            total_downtime: chrono::Duration::seconds(40 + 140),
+            last_flap_ts: Some(ts(160)),
+            current_state: ConnectionState::Active,
+            uptime: None,
+        });
+        assert_eq!(report[1].peer, peer("192.0.2.1", 65001));
+        assert_eq!((report[1].flap_count, report[1].uptime), (0, Some(chrono::Duration::seconds(300))));
+        assert_eq!(report[0].to_string(), "192.0.2.2 (AS65002): 2 flaps, down for 3m 0s, last flap at 2020-09-13 12:29:20 UTC, Active");
+        assert_eq!(report[1].to_string(), "192.0.2.1 (AS65001): 0 flaps, down for 0s, up for 5m 0s");
+    }
+
+    #[test]
+    fn test_peers_in_state() {
+        let ts = DateTime::from_timestamp(1600000000, 0).unwrap();
+        let mut processor = MrtProcessor::new(180, None);
+        for (p, state) in [(peer("192.0.2.2", 1), ConnectionState::Established), (peer("192.0.2.1", 1), ConnectionState::Established), (peer("192.0.2.3", 1), ConnectionState::Active)] {
+            processor.current_state.entry(p).or_default().update_connection_state(ts, state);
+        }
+
+        assert_eq!(processor.established_peers(), vec![&peer("192.0.2.1", 1), &peer("192.0.2.2", 1)]);
+        assert_eq!(processor.peers_in_state(ConnectionState::Active), vec![&peer("192.0.2.3", 1)]);
+        assert!(processor.peers_in_state(ConnectionState::Idle).is_empty());
+        assert_eq!(processor.all_peers().len(), 3);
+    }
+
+    #[test]
+    fn test_process_update_file_report() {
+        let path = write_updates_file("report", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+            elem(1600000002.0, "2001:db8::1", 65002, ElemType::ANNOUNCE, "2001:db8:1::/48"),
+            elem(1600000003.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+        ]);
+
+        let mut processor = MrtProcessor::default();
+        let report = processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.file_path, path.display().to_string());
+        assert_eq!(report.records_processed, 4);
+        assert_eq!(report.announcements, 3);
+        assert_eq!(report.withdrawals, 1);
+        assert_eq!(report.orphan_withdrawals, 0);
+        assert_eq!(report.state_changes, 0);
+        assert_eq!(report.hold_timer_expirations, 0);
+        assert_eq!(report.peers_affected, HashSet::from([peer("192.0.2.1", 65001), peer("2001:db8::1", 65002)]));
+
+        let state = processor.get_current_state();
+        assert_eq!(state[&peer("192.0.2.1", 65001)].announcements().len(), 1);
+        assert_eq!(state[&peer("2001:db8::1", 65002)].announcements().len(), 1);
+    }
+
+    #[test]
+    fn test_process_update_file_from_parser() {
+        let bytes = encode_updates(&[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ]);
+
+        let mut processor = MrtProcessor::default();
+        let report = processor.process_update_file_from_parser(BgpkitParser::from_reader(std::io::Cursor::new(bytes))).unwrap();
+
+        assert_eq!(report.file_path, PARSER_SOURCE);
+        assert_eq!(report.announcements, 2);
+        assert_eq!(processor.get_current_state()[&peer("192.0.2.1", 65001)].announcements().len(), 2);
+    }
+
+    #[test]
+    fn test_process_records() {
+        let bytes = encode_updates(&[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+            elem(1600000002.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+        ]);
+
+        let mut reader = MrtFileReader::new();
+        let mut processor = MrtProcessor::default();
+        let (first, second) = bytes.split_at(bytes.len() / 2);
+        let first_report = processor.process_records(reader.feed(first).records).unwrap();
+        let second_report = processor.process_records(reader.feed(second).records).unwrap();
+
+        assert_eq!(second_report.file_path, RECORDS_SOURCE);
+        assert_eq!(first_report.records_processed + second_report.records_processed, 3);
+        assert_eq!(processor.last_processed_timestamp(), DateTime::from_timestamp(1600000002, 0));
+        let announcements = processor.get_current_state()[&peer("192.0.2.1", 65001)].announcements();
+        assert_eq!(announcements.keys().collect::<Vec<_>>(), vec![&NetworkPrefix::from_str("203.0.113.0/24").unwrap()]);
+
+        let mut fed = MrtProcessor::default();
+        let records_processed: u64 = bytes.chunks(5).map(|chunk| fed.feed_bytes(chunk).unwrap().records_processed).sum();
+        assert_eq!(records_processed, 3);
+        assert_eq!(fed.get_current_state()[&peer("192.0.2.1", 65001)].announcements().len(), 1);
+
+        // A record with an unknown MRT type is skipped, the record after it is still processed
+        let mut corrupt = bytes.clone();
+        corrupt[4..6].copy_from_slice(&0xffffu16.to_be_bytes());
+        let report = MrtProcessor::default().feed_bytes(&corrupt).unwrap();
+        assert_eq!(report.records_skipped, 1);
+        assert_eq!(report.records_processed, 2);
+
+        // Chunks without a complete record keep the cached topology
+        fed.transit_as_ranking();
+        let report = fed.feed_bytes(&[]).unwrap();
+        assert_eq!(report.records_processed, 0);
+        assert!(fed.feed_bytes(&bytes[..5]).is_ok());
+        assert!(fed.topology_cache.get().is_some());
+    }
+
+    #[test]
+    fn test_fork_is_independent() {
+        let path = write_updates_file("fork", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+        ]);
+        let mut processor = MrtProcessor::default();
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let path = write_updates_file("fork_update", &[
+            elem(1600000001.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+            elem(1600000002.0, "192.0.2.2", 65002, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ]);
+        let mut fork = processor.fork();
+        fork.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(processor.all_peers(), vec![&peer("192.0.2.1", 65001)]);
+        assert_eq!(processor.get_current_state()[&peer("192.0.2.1", 65001)].announcements().len(), 1);
+        assert_eq!(processor.top_n_prefixes_by_churn(10).len(), 1);
+        assert_eq!(fork.all_peers().len(), 2);
+        assert!(fork.get_current_state()[&peer("192.0.2.1", 65001)].announcements().is_empty());
+        assert_eq!(fork.top_n_prefixes_by_churn(10).len(), 2);
+    }
+
+    /// A BGP4MP_MESSAGE_AS4 record of an UPDATE from 192.0.2.1 (AS65001) with the given path attributes
+    fn bgp4mp_update_record(ts: u32, attributes: &[u8]) -> Vec<u8> {
+        let mut update = vec![0xff; 16];
+        update.extend_from_slice(&(23 + attributes.len() as u16).to_be_bytes());
+        update.extend_from_slice(&[2, 0, 0]);
+        update.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        update.extend_from_slice(attributes);
+
+        let mut body = [65001u32.to_be_bytes(), 65000u32.to_be_bytes()].concat();
+        body.extend_from_slice(&[0, 0, 0, 1, 192, 0, 2, 1, 192, 0, 2, 254]);
+        body.extend_from_slice(&update);
+
+        let mut record = ts.to_be_bytes().to_vec();
+        record.extend_from_slice(&[0, 16, 0, 4]);
+        record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        record.extend_from_slice(&body);
+        record
+    }
+
+    /// A labeled VPN-IPv4 NLRI entry of a /24
+    fn vpn_nlri(rd: &RouteDistinguisher, prefix: [u8; 3]) -> Vec<u8> {
+        [&[112, 0x00, 0x06, 0x41][..], &rd.0, &prefix].concat()
+    }
+
+    #[test]
+    fn test_vpn_routes() {
+        let rd = RouteDistinguisher([0, 0, 0xfd, 0xe9, 0, 0, 0, 100]);
+        let other_rd = RouteDistinguisher([0, 0, 0xfd, 0xe9, 0, 0, 0, 200]);
+
+        // ORIGIN IGP, AS_PATH 65001 65010 and MP_REACH_NLRI (AFI 1, SAFI 128) with next hop 192.0.2.1
+        let mut announce = vec![0x40, 1, 1, 0, 0x40, 2, 10, 2, 2, 0, 0, 0xfd, 0xe9, 0, 0, 0xfd, 0xf2];
+        let nlri = [vpn_nlri(&rd, [10, 1, 0]), vpn_nlri(&rd, [10, 2, 0]), vpn_nlri(&other_rd, [10, 1, 0])].concat();
+        announce.extend_from_slice(&[0x80, 14, 17 + nlri.len() as u8, 0, 1, 128, 12, 0, 0, 0, 0, 0, 0, 0, 0, 192, 0, 2, 1, 0]);
+        announce.extend_from_slice(&nlri);
+        // MP_UNREACH_NLRI of 10.2.0.0/24 with the first route distinguisher
+        let withdrawn = vpn_nlri(&rd, [10, 2, 0]);
+        let withdraw = [&[0x80, 15, 3 + withdrawn.len() as u8, 0, 1, 128][..], &withdrawn].concat();
+
+        let path = std::env::temp_dir().join(format!("mrt_state_to_state_vpn_{}.mrt", std::process::id()));
+        std::fs::write(&path, [bgp4mp_update_record(1600000000, &announce), bgp4mp_update_record(1600000001, &withdraw)].concat()).unwrap();
+        let mut processor = MrtProcessor::default();
+        let report = processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((report.announcements, report.withdrawals, report.orphan_withdrawals), (3, 1, 0));
+        let peer_state = &processor.get_current_state()[&peer("192.0.2.1", 65001)];
+        assert!(peer_state.announcements().is_empty());
+        assert_eq!(peer_state.vpn_announcements().len(), 2);
+
+        let routes = processor.vpn_routes_for_rd(&rd);
+        assert_eq!(routes.len(), 1);
+        let routes = &routes[&peer("192.0.2.1", 65001)];
+        assert_eq!(routes.iter().map(|(prefix, _)| prefix.to_string()).collect::<Vec<_>>(), vec!["10.1.0.0/24"]);
+        let ann = routes[0].1;
+        assert_eq!(ann.as_path, Some(AsPath::from_sequence([65001, 65010])));
+        assert_eq!(ann.next_hop, Some(IpAddr::from_str("192.0.2.1").unwrap()));
+        assert_eq!(processor.vpn_routes_for_rd(&other_rd)[&peer("192.0.2.1", 65001)].len(), 1);
+        assert!(processor.vpn_routes_for_rd(&RouteDistinguisher([0; 8])).is_empty());
+    }
+
+    #[test]
+    fn test_ahash_processor() {
+        let path = write_updates_file("ahash", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+            elem(1600000002.0, "2001:db8::1", 65002, ElemType::ANNOUNCE, "2001:db8:1::/48"),
+            elem(1600000003.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "203.0.113.0/24"),
+        ]);
+        let mut processor = MrtProcessor::default();
+        processor.process_update_file(&path).unwrap();
+        let mut ahash_processor = MrtProcessor::with_ahash(180, None);
+        ahash_processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ahash_processor.top_n_prefixes_by_churn(10), processor.top_n_prefixes_by_churn(10));
+        let (mut state_json, mut ahash_state_json) = (Vec::new(), Vec::new());
+        processor.save_state_json(&mut state_json).unwrap();
+        ahash_processor.save_state_json(&mut ahash_state_json).unwrap();
+        assert_eq!(ahash_state_json, state_json);
+
+        // The serialized state does not depend on the hasher
+        let mut restored = MrtProcessor::default();
+        restored.load_state_json(ahash_state_json.as_slice()).unwrap();
+        assert_eq!(restored.all_peers(), processor.all_peers());
+    }
+
+    #[test]
+    fn test_max_peers() {
+        let path = write_updates_file("max_peers", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.2", 65002, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000002.0, "192.0.2.3", 65003, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000003.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ]);
+
+        let mut processor = MrtProcessor::default().with_max_peers(2);
+        assert!(!processor.peer_limit_reached());
+        let report = processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(processor.peer_limit_reached());
+        assert_eq!(processor.all_peers(), vec![&peer("192.0.2.1", 65001), &peer("192.0.2.2", 65002)]);
+        assert_eq!(processor.get_current_state()[&peer("192.0.2.1", 65001)].announcements().len(), 2);
+        assert_eq!(report.announcements, 3);
+        assert_eq!(report.peers_affected.len(), 2);
+    }
+
+    #[test]
+    fn test_process_directory() {
+        let dir = std::env::temp_dir().join(format!("mrt_state_to_state_directory_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut rib_encoder = bgpkit_parser::encoder::MrtRibEncoder::new();
+        rib_encoder.process_elem(&elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"));
+        std::fs::write(dir.join("a-bview.mrt"), rib_encoder.export_bytes()).unwrap();
+        std::fs::write(dir.join("b-updates.mrt"), encode_updates(&[
+            elem(1600000300.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ])).unwrap();
+        std::fs::write(dir.join("c-corrupt.mrt"), b"not an mrt file").unwrap();
+        std::fs::write(dir.join("d-updates.mrt"), encode_updates(&[
+            elem(1600000600.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+        ])).unwrap();
+        std::fs::write(dir.join("e-ignored.txt"), b"").unwrap();
+
+        let mut processor = MrtProcessor::default();
+        let results = processor.process_directory(&dir, Some("*.mrt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let files: Vec<String> = results.iter()
+            .map(|result| result.as_ref().map(|report| report.file_path.rsplit('/').next().unwrap().to_string()).unwrap_or_default())
+            .collect();
+        assert_eq!(files, vec!["a-bview.mrt", "b-updates.mrt", "", "d-updates.mrt"]);
+        assert!(results[2].is_err());
+        assert_eq!(results[1].as_ref().unwrap().announcements, 1);
+        assert_eq!(results[3].as_ref().unwrap().withdrawals, 1);
+
+        let state = processor.get_current_state();
+        let prefixes: Vec<String> = state[&peer("192.0.2.1", 65001)].sorted_announcements().iter().map(|(p, _)| p.to_string()).collect();
+        assert_eq!(prefixes, vec!["203.0.113.0/24"]);
+    }
+
+    #[test]
+    fn test_state_json_roundtrip() {
+        let path = write_updates_file("state_json", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.2", 65002, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ]);
+        let mut processor = MrtProcessor::default();
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut json = Vec::new();
+        processor.save_state_json(&mut json).unwrap();
+
+        let path = write_updates_file("state_json_existing", &[
+            elem(1600000000.0, "192.0.2.3", 65003, ElemType::ANNOUNCE, "198.51.100.0/24"),
+        ]);
+        let mut loaded = MrtProcessor::default();
+        loaded.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        loaded.load_state_json(json.as_slice()).unwrap();
+
+        assert_eq!(loaded.all_peers(), vec![&peer("192.0.2.1", 65001), &peer("192.0.2.2", 65002), &peer("192.0.2.3", 65003)]);
+        assert_eq!(loaded.get_current_state()[&peer("192.0.2.2", 65002)].announcements().len(), 1);
+
+        let err = MrtProcessor::default().with_max_peers(1).load_state_json(json.as_slice()).unwrap_err();
+        assert!(matches!(err, ProcessorError::PeerLimitExceeded));
+
+        let json = String::from_utf8(json).unwrap();
+        let named = json.replacen("\"connection_state\":0", "\"connection_state\":\"Idle\"", 1);
+        MrtProcessor::default().load_state_json(named.as_bytes()).unwrap();
+        let invalid = json.replacen("\"connection_state\":0", "\"connection_state\":\"Establishd\"", 1);
+        let err = loaded.load_state_json(invalid.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("invalid value: string \"Establishd\""), "{}", err);
+        let invalid = json.replacen("\"connection_state\":0", "\"connection_state\":6", 1);
+        let err = loaded.load_state_json(invalid.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("invalid value: integer `6`"), "{}", err);
+    }
+
+    #[test]
+    fn test_data_coverage() {
+        let path = write_updates_file("coverage", &[
+            elem(1600000100.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000000.0, "192.0.2.2", 65002, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000300.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ]);
+        let mut processor = MrtProcessor::default();
+        assert_eq!(processor.data_coverage(), None);
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let ts = |secs: i64| DateTime::from_timestamp(secs, 0).unwrap();
+        assert_eq!(processor.data_coverage(), Some((ts(1600000000), ts(1600000300))));
+        let state = &processor.get_current_state()[&peer("192.0.2.1", 65001)];
+        assert_eq!((state.first_seen(), state.last_seen()), (Some(ts(1600000100)), Some(ts(1600000300))));
+    }
+
+    #[test]
+    fn test_extended_timestamp_records() {
+        let path = write_updates_file("bgp4mp_et", &[
+            elem(1600000000.25, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000000.5, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ]);
+
+        // The encoder writes BGP4MP_ET records
+        let record = BgpkitParser::new(path.to_str().unwrap()).unwrap().into_record_iter().next().unwrap();
+        assert_eq!(record.common_header.entry_type, bgpkit_parser::models::EntryType::BGP4MP_ET);
+        assert_eq!(mrt_record_ts(&record), DateTime::from_timestamp(1600000000, 250_000_000).unwrap());
+
+        let mut processor = MrtProcessor::default();
+        let report = processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.announcements, 2);
+        assert_eq!(report.last_timestamp, DateTime::from_timestamp(1600000000, 500_000_000));
+        assert_eq!(processor.last_processed_timestamp(), report.last_timestamp);
+        let state = &processor.get_current_state()[&peer("192.0.2.1", 65001)];
+        let announced: Vec<DateTime<Utc>> = state.sorted_announcements().iter().map(|(_, ann)| ann.timestamp).collect();
+        assert_eq!(announced, vec![DateTime::from_timestamp(1600000000, 250_000_000).unwrap(), DateTime::from_timestamp(1600000000, 500_000_000).unwrap()]);
+    }
+
+    #[test]
+    fn test_last_processed_timestamp() {
+        let mut rib_encoder = bgpkit_parser::encoder::MrtRibEncoder::new();
+        rib_encoder.process_elem(&elem(1600000100.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"));
+        let bview = std::env::temp_dir().join(format!("mrt_state_to_state_last_ts_bview_{}.mrt", std::process::id()));
+        std::fs::write(&bview, rib_encoder.export_bytes()).unwrap();
+        let updates = write_updates_file("last_ts_updates", &[
+            elem(1600000050.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+        ]);
+
+        let mut processor = MrtProcessor::default();
+        assert_eq!(processor.last_processed_timestamp(), None);
+        processor.process_bview(&bview).unwrap();
+        assert_eq!(processor.last_processed_timestamp(), DateTime::from_timestamp(1600000100, 0));
+        // Older update records do not move the timestamp back
+        processor.process_update_file(&updates).unwrap();
+        assert_eq!(processor.last_processed_timestamp(), DateTime::from_timestamp(1600000100, 0));
+
+        std::fs::remove_file(&bview).unwrap();
+        std::fs::remove_file(&updates).unwrap();
+    }
+
+    #[test]
+    fn test_mp_reach_next_hop() {
+        let path = write_updates_file("mp_reach", &[
+            elem(1600000000.0, "2001:db8::1", 65001, ElemType::ANNOUNCE, "2001:db8:1::/48"),
+        ]);
+        let mut processor = MrtProcessor::default();
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let state = &processor.get_current_state()[&peer("2001:db8::1", 65001)];
+        let prefix = NetworkPrefix::from_str("2001:db8:1::/48").unwrap();
+        assert_eq!(state.announcements()[&prefix].next_hop, Some(IpAddr::from_str("2001:db8::1").unwrap()));
+    }
+
+    #[test]
+    fn test_asn_remap_table() {
+        let legacy = BgpElem {
+            as_path: Some(AsPath::from_sequence([65001, AS_TRANS])),
+            ..elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "192.0.2.0/24")
+        };
+        let path = write_updates_file("asn_remap", &[legacy]);
+        let mut processor = MrtProcessor::default().with_asn_remap_table(AsRemapTable(HashMap::from([(AS_TRANS, 200000)])));
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let state = &processor.get_current_state()[&peer("192.0.2.1", 65001)];
+        let prefix = NetworkPrefix::from_str("192.0.2.0/24").unwrap();
+        assert_eq!(state.announcements()[&prefix].as_path, Some(AsPath::from_sequence([65001, 200000])));
+    }
+
+    #[test]
+    fn test_validate_bview_consistency() {
+        let with_path = |prefix: &str, path: &[u32]| BgpElem {
+            as_path: Some(AsPath::from_sequence(path)),
+            ..elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, prefix)
+        };
+        // Two concatenated dumps announce 198.51.100.0/24 twice for the same peer
+        let mut bview = Vec::new();
+        for elems in [vec![with_path("198.51.100.0/24", &[65001, 3356]), with_path("203.0.113.0/24", &[65001])], vec![with_path("198.51.100.0/24", &[65001, 174])]] {
+            let mut rib_encoder = bgpkit_parser::encoder::MrtRibEncoder::new();
+            for e in &elems {
+                rib_encoder.process_elem(e);
+            }
+            bview.extend_from_slice(&rib_encoder.export_bytes());
+        }
+
+        let mut processor = MrtProcessor::default();
+        processor.process_bview_from_parser(BgpkitParser::from_reader(bview.as_slice())).unwrap();
+        assert_eq!(processor.validate_bview_consistency(), vec![BviewInconsistency {
+            peer: peer("192.0.2.1", 65001),
+            prefix: NetworkPrefix::from_str("198.51.100.0/24").unwrap(),
+            count: 2,
+            distinct_as_paths: vec!["65001 3356".to_string(), "65001 174".to_string()],
+        }]);
+    }
+
+    #[test]
+    fn test_orphan_withdrawals() {
+        let path = write_updates_file("orphans", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+            elem(1600000002.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "203.0.113.0/24"),
+            elem(1600000003.0, "192.0.2.2", 65002, ElemType::WITHDRAW, "203.0.113.0/24"),
+        ]);
+
+        let mut processor = MrtProcessor::default();
+        let report = processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.withdrawals, 3);
+        assert_eq!(report.orphan_withdrawals, 2);
+        assert_eq!(processor.get_current_state()[&peer("192.0.2.1", 65001)].orphan_withdrawals(), 1);
+        assert_eq!(processor.total_orphan_withdrawals(), 2);
+    }
+
+    #[test]
+    fn test_processing_stats_unnormalized_prefixes() {
+        // Host bits within the encoded prefix bytes survive the MRT encoding
+        let path = write_updates_file("unnormalized", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "192.0.3.0/23"),
+            elem(1600000001.0, "2001:db8::1", 65002, ElemType::ANNOUNCE, "2001:db8:1::/47"),
+            elem(1600000002.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+        ]);
+        let mut processor = MrtProcessor::default().with_strict(true);
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(processor.processing_stats().unnormalized_prefix_count, 2);
+        let announcements = processor.get_current_state()[&peer("192.0.2.1", 65001)].announcements();
+        assert!(announcements.contains_key(&NetworkPrefix::from_str("192.0.2.0/23").unwrap()));
+        let announcements = processor.get_current_state()[&peer("2001:db8::1", 65002)].announcements();
+        assert!(announcements.contains_key(&NetworkPrefix::from_str("2001:db8::/47").unwrap()));
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let path = write_updates_file("checkpoint", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000002.0, "2001:db8::1", 65002, ElemType::ANNOUNCE, "2001:db8:1::/48"),
+        ]);
+        let mut processor = MrtProcessor::default();
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let checkpoint_path = std::env::temp_dir().join(format!("mrt_state_to_state_checkpoint_{}.bin", std::process::id()));
+        processor.save_checkpoint(&checkpoint_path).unwrap();
+
+        let mut restored = MrtProcessor::default();
+        let ts = restored.load_checkpoint(&checkpoint_path).unwrap();
+        std::fs::remove_file(&checkpoint_path).unwrap();
+
+        assert_eq!(ts, DateTime::from_timestamp(1600000002, 0));
+        assert_eq!(restored.all_peers(), processor.all_peers());
+        let prefix = NetworkPrefix::from_str("198.51.100.0/24").unwrap();
+        let restored_ann = &restored.get_current_state()[&peer("192.0.2.1", 65001)].announcements()[&prefix];
+        assert_eq!(restored_ann.next_hop, Some(IpAddr::from_str("192.0.2.1").unwrap()));
+        assert_eq!(restored_ann.timestamp, DateTime::from_timestamp(1600000000, 0).unwrap());
+        assert_eq!(restored.get_current_state()[&peer("2001:db8::1", 65002)].announcements().len(), 1);
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        // 198.51.100.0/24 is in the bview twice, 192.0.3.0/23 has host bits set
+        let mut bview = Vec::new();
+        for prefix in ["198.51.100.0/24", "198.51.100.0/24"] {
+            let mut rib_encoder = bgpkit_parser::encoder::MrtRibEncoder::new();
+            rib_encoder.process_elem(&elem(1599999990.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, prefix));
+            bview.extend_from_slice(&rib_encoder.export_bytes());
+        }
+        let first = write_updates_file("resume_first", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "192.0.3.0/23"),
+            elem(1600000001.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+        ]);
+        let second = write_updates_file("resume_second", &[
+            elem(1600000002.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000003.0, "2001:db8::1", 65002, ElemType::ANNOUNCE, "2001:db8:1::/48"),
+        ]);
+
+        let mut uninterrupted = MrtProcessor::default();
+        uninterrupted.process_bview_from_parser(BgpkitParser::from_reader(bview.as_slice())).unwrap();
+        uninterrupted.process_update_file(&first).unwrap();
+        let checkpoint_path = std::env::temp_dir().join(format!("mrt_state_to_state_resume_{}.bin", std::process::id()));
+        uninterrupted.save_checkpoint(&checkpoint_path).unwrap();
+        uninterrupted.process_update_file(&second).unwrap();
+
+        let mut resumed = MrtProcessor::default();
+        resumed.load_checkpoint(&checkpoint_path).unwrap();
+        resumed.process_update_file(&second).unwrap();
+        for path in [&first, &second, &checkpoint_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(resumed.top_n_prefixes_by_churn(10), uninterrupted.top_n_prefixes_by_churn(10));
+        assert_eq!(resumed.top_n_prefixes_by_churn(1), vec![(NetworkPrefix::from_str("198.51.100.0/24").unwrap(), 2)]);
+        assert_eq!(resumed.validate_bview_consistency(), uninterrupted.validate_bview_consistency());
+        assert_eq!(resumed.validate_bview_consistency().len(), 1);
+        assert_eq!(resumed.processing_stats(), uninterrupted.processing_stats());
+        assert_eq!(resumed.processing_stats().unnormalized_prefix_count, 1);
+        assert_eq!(resumed.last_processed_timestamp(), uninterrupted.last_processed_timestamp());
+        assert_eq!(resumed.all_peers(), uninterrupted.all_peers());
+    }
+
+    #[test]
+    fn test_validate_files() {
+        let path = write_updates_file("validate", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000005.0, "2001:db8::1", 65002, ElemType::ANNOUNCE, "2001:db8:1::/48"),
+            elem(1600000003.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+        ]);
+        // Cut the last record in half
+        let bytes = std::fs::read(&path).unwrap();
+        let truncated_path = path.with_extension("truncated");
+        std::fs::write(&truncated_path, &bytes[..bytes.len() - 10]).unwrap();
+
+        let files = vec![path.display().to_string(), truncated_path.display().to_string()];
+        let results = MrtProcessor::default().validate_files(&files);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&truncated_path).unwrap();
+
+        let info = results[0].as_ref().unwrap();
+        assert_eq!(info.record_count, 3);
+        assert_eq!(info.first_ts, DateTime::from_timestamp(1600000000, 0).unwrap());
+        assert_eq!(info.last_ts, DateTime::from_timestamp(1600000005, 0).unwrap());
+        assert_eq!(info.peer_count, 2);
+        assert_eq!(info.file_type, crate::util::FileType::Updates);
+        assert_eq!(info.compression, crate::util::CompressionType::None);
+
+        assert_eq!(results[1].as_ref().unwrap_err().records_parsed, 2);
+    }
+
+    #[test]
+    fn test_top_n_prefixes_by_churn() {
+        let path = write_updates_file("churn", &[
+            elem(1600000000.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000001.0, "192.0.2.2", 65002, ElemType::ANNOUNCE, "198.51.100.0/24"),
+            elem(1600000002.0, "192.0.2.1", 65001, ElemType::WITHDRAW, "198.51.100.0/24"),
+            elem(1600000003.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "203.0.113.0/24"),
+            elem(1600000004.0, "192.0.2.1", 65001, ElemType::ANNOUNCE, "192.0.2.0/24"),
+        ]);
+        let mut processor = MrtProcessor::default();
+        processor.process_update_file(&path).unwrap();
+        processor.process_update_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(processor.top_n_prefixes_by_churn(2), vec![
+            (NetworkPrefix::from_str("198.51.100.0/24").unwrap(), 6),
+            (NetworkPrefix::from_str("192.0.2.0/24").unwrap(), 2),
+        ]);
+
+        processor.reset_churn_counters();
+        assert!(processor.top_n_prefixes_by_churn(2).is_empty());
+    }
+}
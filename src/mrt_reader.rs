@@ -0,0 +1,226 @@
+use std::io::{ErrorKind, Read};
+
+use bgpkit_parser::models::MrtRecord;
+use bgpkit_parser::{parse_mrt_record, ParserError};
+use bytes::{Bytes, BytesMut};
+
+use crate::error::ProcessorError;
+
+/// Length of the MRT common header (RFC 6396 §2), not included in its length field
+const COMMON_HEADER_LENGTH: usize = 12;
+
+/// Largest record length accepted from a common header, a larger length means the stream is corrupt
+pub const MAX_RECORD_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Records parsed by a single `MrtFileReader::feed`
+#[derive(Debug, Default)]
+pub struct FeedResult {
+    pub records: Vec<MrtRecord>,
+    /// Errors for records that were skipped, in stream order
+    pub errors: Vec<ProcessorError>,
+}
+
+/// Incremental MRT parser for data that arrives in chunks, e.g. records streamed over a TCP connection.
+///
+/// Bytes are buffered until a record is complete, a partial record is kept for the next `feed`. Pass the records to
+/// `MrtProcessor::process_records`.
+#[derive(Debug, Clone, Default)]
+pub struct MrtFileReader {
+    buffer: BytesMut,
+}
+
+impl MrtFileReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `data` to the buffer and parse all records that are complete.
+    ///
+    /// A record that fails to parse is skipped and reported in `FeedResult::errors`, parsing continues with the record
+    /// after it. A header declaring more than `MAX_RECORD_LENGTH` bytes can not be skipped, the buffer is discarded.
+    pub fn feed(&mut self, data: &[u8]) -> FeedResult {
+        self.buffer.extend_from_slice(data);
+        let mut result = FeedResult::default();
+        loop {
+            match self.next_record_length() {
+                Ok(Some(length)) => {
+                    let record = self.buffer.split_to(length);
+                    match parse_mrt_record(&mut &record[..]) {
+                        Ok(record) => result.records.push(record),
+                        Err(e) => result.errors.push(e.into()),
+                    }
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    self.buffer.clear();
+                    result.errors.push(e);
+                    break;
+                },
+            }
+        }
+        result
+    }
+
+    /// Number of buffered bytes of a record that is not complete yet
+    pub fn pending_bytes(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Length of the first buffered record including the common header, `None` while it is incomplete
+    fn next_record_length(&self) -> Result<Option<usize>, ProcessorError> {
+        let Some(header) = self.buffer.get(..COMMON_HEADER_LENGTH) else {
+            return Ok(None);
+        };
+        let length = COMMON_HEADER_LENGTH + body_length(header)?;
+        Ok((self.buffer.len() >= length).then_some(length))
+    }
+}
+
+/// Iterator over the records of an MRT file together with their raw bytes, including the common header.
+///
+/// The raw bytes hold what `bgpkit_parser` drops while parsing, e.g. the VPN routes read by `decode_vpn_update`. Like
+/// the record iterator of `BgpkitParser`, records that fail to parse are logged and skipped, a read error or a record
+/// cut off by the end of the file ends the iteration.
+pub struct RawRecordIter<R> {
+    reader: R,
+}
+
+impl<R: Read> RawRecordIter<R> {
+    pub fn new(reader: R) -> Self {
+        RawRecordIter { reader }
+    }
+
+    // The next record including its common header, `None` at the end of the file
+    fn next_raw(&mut self) -> Result<Option<Bytes>, ProcessorError> {
+        let mut raw = BytesMut::zeroed(COMMON_HEADER_LENGTH);
+        match self.reader.read_exact(&mut raw) {
+            Ok(()) => {},
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let length = body_length(&raw)?;
+        raw.resize(COMMON_HEADER_LENGTH + length, 0);
+        self.reader.read_exact(&mut raw[COMMON_HEADER_LENGTH..])?;
+        Ok(Some(raw.freeze()))
+    }
+}
+
+impl<R: Read> Iterator for RawRecordIter<R> {
+    type Item = (MrtRecord, Bytes);
+
+    fn next(&mut self) -> Option<(MrtRecord, Bytes)> {
+        loop {
+            let raw = match self.next_raw() {
+                Ok(raw) => raw?,
+                Err(e) => {
+                    log::error!("Stopped reading MRT records: {}", e);
+                    return None;
+                },
+            };
+            match parse_mrt_record(&mut &raw[..]) {
+                Ok(record) => return Some((record, raw)),
+                Err(e) => log::warn!("Skipping MRT record: {}", ProcessorError::from(e)),
+            }
+        }
+    }
+}
+
+// Length of the record body from a common header
+fn body_length(header: &[u8]) -> Result<usize, ProcessorError> {
+    let length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    if length > MAX_RECORD_LENGTH {
+        return Err(ParserError::ParseError(format!("MRT record length {} exceeds the maximum of {} bytes", length, MAX_RECORD_LENGTH)).into());
+    }
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bgpkit_parser::encoder::MrtUpdatesEncoder;
+    use bgpkit_parser::models::{AsPath, BgpElem, ElemType, NetworkPrefix};
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn encode_updates() -> Vec<u8> {
+        let mut encoder = MrtUpdatesEncoder::new();
+        for (ts, prefix) in [(1600000000.0, "198.51.100.0/24"), (1600000001.0, "203.0.113.0/24"), (1600000002.0, "2001:db8:1::/48")] {
+            encoder.process_elem(&BgpElem {
+                timestamp: ts,
+                elem_type: ElemType::ANNOUNCE,
+                peer_ip: IpAddr::from_str("192.0.2.1").unwrap(),
+                peer_asn: 65001.into(),
+                prefix: NetworkPrefix::from_str(prefix).unwrap(),
+                next_hop: Some(IpAddr::from_str("192.0.2.1").unwrap()),
+                as_path: Some(AsPath::from_sequence([65001, 65002])),
+                ..Default::default()
+            });
+        }
+        encoder.export_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_feed_in_chunks() {
+        let data = encode_updates();
+        let mut reader = MrtFileReader::new();
+        let mut records = Vec::new();
+        for chunk in data.chunks(7) {
+            let result = reader.feed(chunk);
+            assert!(result.errors.is_empty());
+            records.extend(result.records);
+        }
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].common_header.timestamp, 1600000002);
+        assert_eq!(reader.pending_bytes(), 0);
+
+        // A truncated record stays buffered
+        assert_eq!(reader.feed(&data[..data.len() - 1]).records.len(), 2);
+        assert!(reader.pending_bytes() > 0);
+        assert_eq!(reader.feed(&data[data.len() - 1..]).records.len(), 1);
+    }
+
+    #[test]
+    fn test_feed_invalid_records() {
+        let data = encode_updates();
+        let first_length = COMMON_HEADER_LENGTH + u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+
+        // An unknown MRT type in the second record is skipped, the records around it are kept
+        let mut corrupt = data.clone();
+        corrupt[first_length + 4..first_length + 6].copy_from_slice(&0xffffu16.to_be_bytes());
+        let mut reader = MrtFileReader::new();
+        let result = reader.feed(&corrupt);
+        assert_eq!(result.records.len(), 2);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.records[1].common_header.timestamp, 1600000002);
+        assert_eq!(reader.pending_bytes(), 0);
+
+        // A bogus length is not buffered, the reader starts over with the next data
+        let mut oversized = data[..COMMON_HEADER_LENGTH].to_vec();
+        oversized[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        let result = reader.feed(&oversized);
+        assert!(result.records.is_empty());
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(reader.pending_bytes(), 0);
+        assert_eq!(reader.feed(&data).records.len(), 3);
+    }
+
+    #[test]
+    fn test_raw_record_iter() {
+        let data = encode_updates();
+        let first_length = COMMON_HEADER_LENGTH + u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+
+        let records: Vec<(MrtRecord, Bytes)> = RawRecordIter::new(data.as_slice()).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].1, data[..first_length]);
+        assert_eq!(records[2].0.common_header.timestamp, 1600000002);
+        assert_eq!(records.iter().map(|(_, raw)| raw.len()).sum::<usize>(), data.len());
+
+        // An unparsable record is skipped, a record cut off by the end of the data ends the iteration
+        let mut corrupt = data.clone();
+        corrupt[4..6].copy_from_slice(&0xffffu16.to_be_bytes());
+        corrupt.truncate(data.len() - 1);
+        let records: Vec<(MrtRecord, Bytes)> = RawRecordIter::new(corrupt.as_slice()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0.common_header.timestamp, 1600000001);
+    }
+}
@@ -1,4 +1,42 @@
-use bgpkit_parser::models::{AsPath, AsPathSegment, AttrRaw, AttrType};
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+use crate::bgp_state::{RouteDistinguisher, AS_TRANS};
+use crate::error::ProcessorError;
+use bgpkit_parser::models::{AsPath, AsPathSegment, Asn, AttrRaw, AttrType, BgpElem, Community, ElemType, EntryType, LargeCommunity, MetaCommunity, Nlri, NetworkPrefix};
+use ipnet::IpNet;
+
+/// Errors while decoding a raw path attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeParseError {
+    /// The attribute is shorter than its contents require
+    Truncated { expected: usize, available: usize },
+    /// The attribute is longer than its fixed length
+    InvalidLength { expected: usize, actual: usize },
+    /// Unknown AS path segment type
+    InvalidSegmentType(u8),
+    /// Prefix length in bits of an NLRI entry that does not fit the address family
+    InvalidPrefixLength(u8),
+}
+
+impl fmt::Display for AttributeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeParseError::Truncated { expected, available } => {
+                write!(f, "Truncated attribute: expected {} bytes, {} available", expected, available)
+            },
+            AttributeParseError::InvalidLength { expected, actual } => {
+                write!(f, "Invalid attribute length: expected {} bytes, got {}", expected, actual)
+            },
+            AttributeParseError::InvalidSegmentType(t) => write!(f, "Invalid AS path segment type: {}", t),
+            AttributeParseError::InvalidPrefixLength(bits) => write!(f, "Invalid NLRI prefix length: {} bits", bits),
+        }
+    }
+}
+
+impl std::error::Error for AttributeParseError {}
 
 
 pub fn handle_attributes(attr_val: Option<Vec<AttrRaw>>) -> Option<Vec<u8>> {
@@ -35,3 +73,494 @@ pub fn format_as_path(path: AsPath) -> Vec<String> {
 
     result
 }
+
+/// Separator style of `as_path_to_string`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegexStyle {
+    /// Space separated, as in IOS-XR `show bgp`
+    Cisco,
+    /// Underscore separated
+    Juniper,
+}
+
+/// AS path as matched by router AS path regular expressions: AS sets as `{ASN1,ASN2}`, confederation segments are
+/// left out
+pub fn as_path_to_string(path: &AsPath, style: RegexStyle) -> String {
+    let separator = match style {
+        RegexStyle::Cisco => " ",
+        RegexStyle::Juniper => "_",
+    };
+
+    let mut tokens = Vec::with_capacity(path.len());
+    for segment in path.iter_segments() {
+        match segment {
+            AsPathSegment::AsSequence(asns) => tokens.extend(asns.iter().map(|asn| asn.to_string())),
+            AsPathSegment::AsSet(asns) => {
+                tokens.push(format!("{{{}}}", asns.iter().map(|asn| asn.to_string()).collect::<Vec<String>>().join(",")));
+            },
+            AsPathSegment::ConfedSequence(_) | AsPathSegment::ConfedSet(_) => {},
+        }
+    }
+    tokens.join(separator)
+}
+
+/// AS path in the Cisco style of `as_path_to_string`
+pub fn format_as_path_regex_style(path: &AsPath) -> String {
+    as_path_to_string(path, RegexStyle::Cisco)
+}
+
+/// Origin AS of a path, the last AS of the final segment.
+///
+/// Paths ending in an AS set have no single origin and return `None`.
+pub fn as_path_origin(path: &AsPath) -> Option<u32> {
+    match path.iter_segments().last()? {
+        AsPathSegment::AsSequence(asns) | AsPathSegment::ConfedSequence(asns) => asns.last().map(|asn| asn.to_u32()),
+        AsPathSegment::AsSet(_) | AsPathSegment::ConfedSet(_) => None,
+    }
+}
+
+/// Replacement ASNs for paths from collectors that do not send AS4_PATH, old ASN to new ASN.
+///
+/// Only the entry for AS_TRANS is applied, see `remap_path`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AsRemapTable(pub HashMap<u32, u32>);
+
+impl AsRemapTable {
+    /// Replace the AS_TRANS hops of a path whose 4-byte ASNs were not merged with the ASN mapped to AS_TRANS.
+    ///
+    /// Other ASNs are real 2-byte ASNs and are kept even if they are in the table.
+    pub fn remap_path(&self, path: &mut AsPath) {
+        let Some(new_asn) = self.0.get(&AS_TRANS) else {
+            return;
+        };
+        for segment in path.iter_segments_mut() {
+            for asn in segment.iter_mut().filter(|asn| asn.to_u32() == AS_TRANS) {
+                *asn = (*new_asn).into();
+            }
+        }
+    }
+}
+
+/// Load an ASN remapping table from a CSV file with `old_asn,new_asn` lines.
+///
+/// Empty lines, lines starting with `#` and a header line are skipped.
+pub fn load_remap_table<P: AsRef<Path>>(path: P) -> Result<AsRemapTable, ProcessorError> {
+    let content = std::fs::read_to_string(path.as_ref())?;
+    let mut table = HashMap::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("old_asn")) {
+            continue;
+        }
+        let parsed = line.split_once(',')
+            .and_then(|(old, new)| Some((old.trim().parse::<u32>().ok()?, new.trim().parse::<u32>().ok()?)));
+        let Some((old_asn, new_asn)) = parsed else {
+            return Err(ProcessorError::ConfigError(format!("Invalid ASN remapping in {} line {}: {}", path.as_ref().display(), i + 1, line)));
+        };
+        table.insert(old_asn, new_asn);
+    }
+
+    Ok(AsRemapTable(table))
+}
+
+/// Decode the value of an AS4_PATH attribute (RFC 6793): segments with 4-byte ASNs
+pub fn decode_as4_path(raw: &[u8]) -> Result<AsPath, AttributeParseError> {
+    let mut segments = Vec::new();
+    let mut rest = raw;
+
+    while !rest.is_empty() {
+        if rest.len() < 2 {
+            return Err(AttributeParseError::Truncated { expected: 2, available: rest.len() });
+        }
+        let (segment_type, count) = (rest[0], rest[1] as usize);
+        let length = 2 + 4 * count;
+        if rest.len() < length {
+            return Err(AttributeParseError::Truncated { expected: length, available: rest.len() });
+        }
+
+        let asns: Vec<Asn> = rest[2..length]
+            .chunks_exact(4)
+            .map(|chunk| Asn::new_32bit(u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])))
+            .collect();
+        segments.push(match segment_type {
+            1 => AsPathSegment::AsSet(asns),
+            2 => AsPathSegment::AsSequence(asns),
+            3 => AsPathSegment::ConfedSequence(asns),
+            4 => AsPathSegment::ConfedSet(asns),
+            t => return Err(AttributeParseError::InvalidSegmentType(t)),
+        });
+        rest = &rest[length..];
+    }
+
+    Ok(AsPath::from_segments(segments))
+}
+
+/// Decode the value of an AS4_AGGREGATOR attribute (RFC 6793): 4-byte ASN and IPv4 address
+pub fn decode_as4_aggregator(raw: &[u8]) -> Result<(u32, IpAddr), AttributeParseError> {
+    if raw.len() < 8 {
+        return Err(AttributeParseError::Truncated { expected: 8, available: raw.len() });
+    }
+    if raw.len() > 8 {
+        return Err(AttributeParseError::InvalidLength { expected: 8, actual: raw.len() });
+    }
+
+    let asn = u32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]);
+    Ok((asn, IpAddr::V4(Ipv4Addr::new(raw[4], raw[5], raw[6], raw[7]))))
+}
+
+/// Find the raw value of an attribute type in a list of unparsed attributes
+pub fn find_raw_attribute(attrs: &[AttrRaw], attr_type: AttrType) -> Option<&[u8]> {
+    attrs.iter().find(|attr| attr.attr_type == attr_type).map(|attr| attr.bytes.as_slice())
+}
+
+/// SAFIs of VPN routes (RFC 4364, RFC 6514), `bgpkit_parser` drops MP_REACH_NLRI and MP_UNREACH_NLRI attributes with these
+const VPN_SAFIS: [u8; 2] = [128, 129];
+
+/// Bits of the label and route distinguisher in front of the prefix of a VPN NLRI entry
+const VPN_NLRI_PREFIX_OFFSET_BITS: usize = 88;
+
+/// VPN routes of a BGP4MP UPDATE record, see `decode_vpn_update`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VpnUpdate {
+    /// Next hop of the MP_REACH_NLRI attribute without its route distinguisher
+    pub next_hop: Option<IpAddr>,
+    pub announced: Vec<(RouteDistinguisher, NetworkPrefix)>,
+    pub withdrawn: Vec<(RouteDistinguisher, NetworkPrefix)>,
+}
+
+impl VpnUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.announced.is_empty() && self.withdrawn.is_empty()
+    }
+}
+
+/// Decode the VPN routes (SAFI 128 and 129) of a raw MRT record including its common header.
+///
+/// `bgpkit_parser` drops these routes while parsing, so they are read from the MP_REACH_NLRI and MP_UNREACH_NLRI
+/// attributes of BGP4MP UPDATE messages directly. Records of other types and other messages give an empty update.
+pub fn decode_vpn_update(record: &[u8]) -> Result<VpnUpdate, AttributeParseError> {
+    let mut rest = record;
+    let header = take(&mut rest, 12)?;
+    let entry_type = u16::from_be_bytes([header[4], header[5]]);
+    let subtype = u16::from_be_bytes([header[6], header[7]]);
+    if entry_type == EntryType::BGP4MP_ET as u16 {
+        // Microseconds of the timestamp
+        take(&mut rest, 4)?;
+    } else if entry_type != EntryType::BGP4MP as u16 {
+        return Ok(VpnUpdate::default());
+    }
+
+    // BGP4MP message subtypes (RFC 6396 §4.4, RFC 8050): ASN length and ADD-PATH
+    let (asn_len, add_path) = match subtype {
+        1 | 6 => (2, false),
+        4 | 7 => (4, false),
+        8 | 10 => (2, true),
+        9 | 11 => (4, true),
+        _ => return Ok(VpnUpdate::default()),
+    };
+    // Peer and local ASN, interface index
+    take(&mut rest, 2 * asn_len + 2)?;
+    let afi = take(&mut rest, 2)?;
+    let address_len = match u16::from_be_bytes([afi[0], afi[1]]) {
+        1 => 4,
+        2 => 16,
+        _ => return Ok(VpnUpdate::default()),
+    };
+    // Peer and local address
+    take(&mut rest, 2 * address_len)?;
+
+    // Marker, length and type of the BGP message
+    let bgp_header = take(&mut rest, 19)?;
+    if bgp_header[18] != 2 {
+        return Ok(VpnUpdate::default());
+    }
+    let withdrawn_len = take_u16(&mut rest)? as usize;
+    take(&mut rest, withdrawn_len)?;
+    let attributes_len = take_u16(&mut rest)? as usize;
+    let mut attributes = take(&mut rest, attributes_len)?;
+
+    let mut update = VpnUpdate::default();
+    while !attributes.is_empty() {
+        let flags_and_type = take(&mut attributes, 2)?;
+        let (flags, attr_type) = (flags_and_type[0], flags_and_type[1]);
+        // Extended length flag
+        let length = match flags & 0x10 {
+            0 => take(&mut attributes, 1)?[0] as usize,
+            _ => take_u16(&mut attributes)? as usize,
+        };
+        let mut value = take(&mut attributes, length)?;
+        match attr_type {
+            14 => {
+                let (afi, safi) = (take_u16(&mut value)?, take(&mut value, 1)?[0]);
+                if !VPN_SAFIS.contains(&safi) {
+                    continue;
+                }
+                let next_hop_len = take(&mut value, 1)?[0] as usize;
+                update.next_hop = vpn_next_hop(take(&mut value, next_hop_len)?);
+                // Reserved
+                take(&mut value, 1)?;
+                update.announced.extend(decode_vpn_nlri(value, afi, add_path)?);
+            },
+            15 => {
+                let (afi, safi) = (take_u16(&mut value)?, take(&mut value, 1)?[0]);
+                if VPN_SAFIS.contains(&safi) {
+                    update.withdrawn.extend(decode_vpn_nlri(value, afi, add_path)?);
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(update)
+}
+
+/// Decode labeled VPN NLRI entries (RFC 4364 §4.3.4): length in bits, a single label, route distinguisher and prefix.
+///
+/// Entries of an ADD-PATH session start with their path identifier (RFC 7911).
+fn decode_vpn_nlri(mut rest: &[u8], afi: u16, add_path: bool) -> Result<Vec<(RouteDistinguisher, NetworkPrefix)>, AttributeParseError> {
+    let max_prefix_len = if afi == 1 { 32 } else { 128 };
+    let mut routes = Vec::new();
+    while !rest.is_empty() {
+        let path_id = match add_path {
+            true => u32::from_be_bytes(take(&mut rest, 4)?.try_into().unwrap()),
+            false => 0,
+        };
+        let bits = take(&mut rest, 1)?[0];
+        let prefix_len = (bits as usize).checked_sub(VPN_NLRI_PREFIX_OFFSET_BITS)
+            .filter(|len| *len <= max_prefix_len)
+            .ok_or(AttributeParseError::InvalidPrefixLength(bits))?;
+        let entry = take(&mut rest, (bits as usize).div_ceil(8))?;
+
+        let rd = RouteDistinguisher(entry[3..11].try_into().unwrap());
+        let address = match afi {
+            1 => {
+                let mut octets = [0; 4];
+                octets[..entry.len() - 11].copy_from_slice(&entry[11..]);
+                IpAddr::from(octets)
+            },
+            _ => {
+                let mut octets = [0; 16];
+                octets[..entry.len() - 11].copy_from_slice(&entry[11..]);
+                IpAddr::from(octets)
+            },
+        };
+        let prefix = IpNet::new(address, prefix_len as u8).map_err(|_| AttributeParseError::InvalidPrefixLength(bits))?;
+        routes.push((rd, NetworkPrefix::new(prefix, path_id)));
+    }
+    Ok(routes)
+}
+
+// Address of a VPN next hop, which starts with a route distinguisher. For a global and link-local pair the global
+// address is used.
+fn vpn_next_hop(raw: &[u8]) -> Option<IpAddr> {
+    match raw.get(8..)? {
+        [a, b, c, d] => Some(IpAddr::from([*a, *b, *c, *d])),
+        address if address.len() >= 16 => Some(IpAddr::from(<[u8; 16]>::try_from(&address[..16]).unwrap())),
+        _ => None,
+    }
+}
+
+// Split `n` bytes off the front of `rest`
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> Result<&'a [u8], AttributeParseError> {
+    if rest.len() < n {
+        return Err(AttributeParseError::Truncated { expected: n, available: rest.len() });
+    }
+    let (head, tail) = rest.split_at(n);
+    *rest = tail;
+    Ok(head)
+}
+
+fn take_u16(rest: &mut &[u8]) -> Result<u16, AttributeParseError> {
+    take(rest, 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Well-known communities with their names (RFC 1997, RFC 7999, RFC 8326)
+const WELL_KNOWN_COMMUNITIES: &[(u32, u16, &str)] = &[
+    (65535, 0, "GRACEFUL_SHUTDOWN"),
+    (65535, 666, "BLACKHOLE"),
+];
+
+/// Canonical string representation of a community.
+///
+/// Standard communities are `ASN:value` or the name of a well-known community (e.g. `NO_EXPORT`, `BLACKHOLE`), large
+/// communities `ASN:local1:local2` and extended communities start with their type code.
+pub fn format_community(c: &MetaCommunity) -> String {
+    match c {
+        MetaCommunity::Plain(Community::NoExport) => "NO_EXPORT".to_string(),
+        MetaCommunity::Plain(Community::NoAdvertise) => "NO_ADVERTISE".to_string(),
+        MetaCommunity::Plain(Community::NoExportSubConfed) => "NO_EXPORT_SUBCONFED".to_string(),
+        MetaCommunity::Plain(Community::Custom(asn, value)) => WELL_KNOWN_COMMUNITIES.iter()
+            .find(|(known_asn, known_value, _)| *known_asn == asn.to_u32() && known_value == value)
+            .map(|(_, _, name)| name.to_string())
+            .unwrap_or_else(|| format!("{}:{}", asn.to_u32(), value)),
+        MetaCommunity::Large(large) => format_large_community(large),
+        MetaCommunity::Extended(extended) => extended.to_string(),
+        MetaCommunity::Ipv6Extended(extended) => extended.to_string(),
+    }
+}
+
+/// Large community as `ASN:local1:local2` (RFC 8092)
+pub fn format_large_community(c: &LargeCommunity) -> String {
+    format!("{}:{}:{}", c.global_admin, c.local_data[0], c.local_data[1])
+}
+
+/// Set the next hop of the announcements from an MP_REACH_NLRI attribute to the next hop of that attribute.
+///
+/// bgpkit_parser uses the NEXT_HOP attribute for all prefixes of an update, which is missing or wrong for the prefixes
+/// of MP_REACH_NLRI, e.g. IPv6 prefixes or IPv4 prefixes with an IPv6 next hop (RFC 5549). For a global and
+/// link-local pair the global address is used.
+pub fn set_mp_reach_next_hop(elems: &mut [BgpElem], nlri: &Nlri) {
+    let Some(next_hop) = nlri.next_hop.map(|next_hop| next_hop.addr()) else {
+        return;
+    };
+
+    let prefixes: HashSet<&NetworkPrefix> = nlri.prefixes.iter().collect();
+    for elem in elems.iter_mut().filter(|elem| elem.elem_type == ElemType::ANNOUNCE && prefixes.contains(&elem.prefix)) {
+        elem.next_hop = Some(next_hop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_as4_path() {
+        let raw = [2, 2, 0, 0, 0xfd, 0xe9, 0x00, 0x03, 0x0d, 0x40, 1, 1, 0, 0, 0, 1];
+        let path = decode_as4_path(&raw).unwrap();
+        assert_eq!(path, AsPath::from_segments(vec![
+            AsPathSegment::sequence([65001, 200000]),
+            AsPathSegment::set([1]),
+        ]));
+
+        assert_eq!(decode_as4_path(&raw[..5]), Err(AttributeParseError::Truncated { expected: 10, available: 5 }));
+        assert_eq!(decode_as4_path(&[9, 0]), Err(AttributeParseError::InvalidSegmentType(9)));
+    }
+
+    #[test]
+    fn test_set_mp_reach_next_hop() {
+        use bgpkit_parser::models::{Afi, NextHopAddress, Safi};
+        use std::str::FromStr;
+
+        let announce = |prefix: &str, next_hop: Option<&str>| BgpElem {
+            prefix: NetworkPrefix::from_str(prefix).unwrap(),
+            next_hop: next_hop.map(|ip| IpAddr::from_str(ip).unwrap()),
+            ..Default::default()
+        };
+        // IPv4 prefix in MP_REACH_NLRI with an IPv6 next hop next to a classic NLRI prefix
+        let mut elems = vec![announce("198.51.100.0/24", Some("192.0.2.1")), announce("203.0.113.0/24", Some("192.0.2.1"))];
+        let nlri = Nlri {
+            afi: Afi::Ipv4,
+            safi: Safi::Unicast,
+            next_hop: Some(NextHopAddress::Ipv6LinkLocal("2001:db8::1".parse().unwrap(), "fe80::1".parse().unwrap())),
+            prefixes: vec![NetworkPrefix::from_str("203.0.113.0/24").unwrap()],
+        };
+        set_mp_reach_next_hop(&mut elems, &nlri);
+
+        assert_eq!(elems[0].next_hop, Some(IpAddr::from_str("192.0.2.1").unwrap()));
+        assert_eq!(elems[1].next_hop, Some(IpAddr::from_str("2001:db8::1").unwrap()));
+    }
+
+    #[test]
+    fn test_as_path_to_string() {
+        let path = AsPath::from_segments(vec![
+            AsPathSegment::ConfedSequence(vec![64512.into()]),
+            AsPathSegment::AsSequence(vec![65001.into(), 3356.into()]),
+            AsPathSegment::AsSet(vec![65010.into(), 65011.into()]),
+        ]);
+        assert_eq!(format_as_path_regex_style(&path), "65001 3356 {65010,65011}");
+        assert_eq!(as_path_to_string(&path, RegexStyle::Juniper), "65001_3356_{65010,65011}");
+        assert_eq!(as_path_to_string(&AsPath::new(), RegexStyle::Cisco), "");
+    }
+
+    #[test]
+    fn test_as_path_origin() {
+        assert_eq!(as_path_origin(&AsPath::from_sequence([65001, 3356, 65010])), Some(65010));
+        assert_eq!(as_path_origin(&AsPath::new()), None);
+
+        let aggregated = AsPath::from_segments(vec![
+            AsPathSegment::AsSequence(vec![65001.into()]),
+            AsPathSegment::AsSet(vec![65010.into(), 65011.into()]),
+        ]);
+        assert_eq!(as_path_origin(&aggregated), None);
+    }
+
+    #[test]
+    fn test_remap_table() {
+        let path = std::env::temp_dir().join(format!("mrt_state_to_state_remap_{}.csv", std::process::id()));
+        std::fs::write(&path, "old_asn,new_asn\n# legacy peers\n23456,200000\n65010, 65020\n").unwrap();
+        let table = load_remap_table(&path).unwrap();
+        assert_eq!(table.0, HashMap::from([(23456, 200000), (65010, 65020)]));
+
+        // 65010 is a table key but a real 2-byte ASN in this path, only the AS_TRANS hop is replaced
+        let mut legacy = AsPath::from_sequence([65001, 23456, 65010]);
+        table.remap_path(&mut legacy);
+        assert_eq!(legacy, AsPath::from_sequence([65001, 200000, 65010]));
+
+        // Paths without AS_TRANS are left alone
+        let mut merged = AsPath::from_sequence([65001, 65010]);
+        table.remap_path(&mut merged);
+        assert_eq!(merged, AsPath::from_sequence([65001, 65010]));
+
+        // Without an AS_TRANS mapping nothing is replaced
+        let mut unmapped = AsPath::from_sequence([65001, 23456]);
+        AsRemapTable(HashMap::from([(65001, 65002)])).remap_path(&mut unmapped);
+        assert_eq!(unmapped, AsPath::from_sequence([65001, 23456]));
+
+        std::fs::write(&path, "23456;200000\n").unwrap();
+        assert!(matches!(load_remap_table(&path), Err(ProcessorError::ConfigError(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_format_community() {
+        use bgpkit_parser::models::{ExtendedCommunity, TwoOctetAsExtCommunity};
+
+        assert_eq!(format_community(&MetaCommunity::Plain(Community::Custom(65001.into(), 100))), "65001:100");
+        assert_eq!(format_community(&MetaCommunity::Plain(Community::Custom(65535.into(), 666))), "BLACKHOLE");
+        assert_eq!(format_community(&MetaCommunity::Plain(Community::NoExport)), "NO_EXPORT");
+        assert_eq!(format_community(&MetaCommunity::Plain(Community::NoAdvertise)), "NO_ADVERTISE");
+        assert_eq!(format_community(&MetaCommunity::Large(LargeCommunity::new(200000, [1, 2]))), "200000:1:2");
+
+        let route_target = ExtendedCommunity::TransitiveTwoOctetAs(TwoOctetAsExtCommunity {
+            subtype: 2,
+            global_admin: 65001.into(),
+            local_admin: [0, 0, 0, 100],
+        });
+        assert_eq!(format_community(&MetaCommunity::Extended(route_target)), "0:2:65001:00000064");
+    }
+
+    #[test]
+    fn test_decode_as4_aggregator() {
+        let raw = [0x00, 0x03, 0x0d, 0x40, 192, 0, 2, 1];
+        assert_eq!(decode_as4_aggregator(&raw), Ok((200000, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))));
+        assert_eq!(decode_as4_aggregator(&raw[..7]), Err(AttributeParseError::Truncated { expected: 8, available: 7 }));
+        let oversized = [&raw[..], &[0]].concat();
+        assert_eq!(decode_as4_aggregator(&oversized), Err(AttributeParseError::InvalidLength { expected: 8, actual: 9 }));
+    }
+
+    #[test]
+    fn test_decode_vpn_nlri() {
+        use std::str::FromStr;
+
+        let rd = RouteDistinguisher([0, 0, 0xfd, 0xe9, 0, 0, 0, 100]);
+        // 10.1.0.0/24 and 2001:db8::/32, each with label 100 and the route distinguisher 65001:100
+        let v4 = [&[112, 0x00, 0x06, 0x41][..], &rd.0, &[10, 1, 0]].concat();
+        let v6 = [&[120, 0x00, 0x06, 0x41][..], &rd.0, &[0x20, 0x01, 0x0d, 0xb8]].concat();
+        assert_eq!(decode_vpn_nlri(&[v4.as_slice(), &v4].concat(), 1, false).unwrap(), vec![
+            (rd, NetworkPrefix::from_str("10.1.0.0/24").unwrap()),
+            (rd, NetworkPrefix::from_str("10.1.0.0/24").unwrap()),
+        ]);
+        assert_eq!(decode_vpn_nlri(&v6, 2, false).unwrap(), vec![(rd, NetworkPrefix::from_str("2001:db8::/32").unwrap())]);
+        let with_path_id = [&[0, 0, 0, 7][..], &v4].concat();
+        assert_eq!(decode_vpn_nlri(&with_path_id, 1, true).unwrap(), vec![(rd, NetworkPrefix::new("10.1.0.0/24".parse().unwrap(), 7))]);
+
+        assert_eq!(decode_vpn_nlri(&v4[..13], 1, false), Err(AttributeParseError::Truncated { expected: 14, available: 12 }));
+        // Too short for a label and route distinguisher, longer than an IPv4 prefix
+        assert_eq!(decode_vpn_nlri(&[80], 1, false), Err(AttributeParseError::InvalidPrefixLength(80)));
+        assert_eq!(decode_vpn_nlri(&[121], 1, false), Err(AttributeParseError::InvalidPrefixLength(121)));
+
+        assert_eq!(vpn_next_hop(&[0, 0, 0, 0, 0, 0, 0, 0, 192, 0, 2, 1]), Some(IpAddr::from([192, 0, 2, 1])));
+        assert_eq!(vpn_next_hop(&[0; 4]), None);
+    }
+}
@@ -0,0 +1,104 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::sync::RwLock;
+use tower_service::Service;
+
+use crate::error::ProcessorError;
+use crate::export::looking_glass::{looking_glass_query, LookingGlassQuery, LookingGlassResponse};
+use crate::mrt_processor::{FileProcessingReport, MrtProcessor};
+
+/// Shared processor for async servers: looking glass queries run concurrently under a read lock, file processing
+/// takes the write lock on the blocking thread pool.
+///
+/// Clones share the same processor, as expected from a `tower::Service`.
+#[derive(Clone)]
+pub struct AsyncMrtProcessor {
+    inner: Arc<RwLock<MrtProcessor>>,
+}
+
+impl AsyncMrtProcessor {
+    pub fn new(processor: MrtProcessor) -> Self {
+        AsyncMrtProcessor { inner: Arc::new(RwLock::new(processor)) }
+    }
+
+    /// Process an update file, queries wait until it is done
+    pub async fn process_update_file(&self, path: PathBuf) -> Result<FileProcessingReport, ProcessorError> {
+        self.write(move |processor| processor.process_update_file(path)).await
+    }
+
+    /// Load a bview, replacing the current state
+    pub async fn process_bview(&self, path: PathBuf) -> Result<(), ProcessorError> {
+        self.write(move |processor| processor.process_bview(path)).await
+    }
+
+    // Run `f` with exclusive access to the processor without blocking the async runtime
+    async fn write<T, F>(&self, f: F) -> Result<T, ProcessorError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut MrtProcessor) -> Result<T, ProcessorError> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&mut inner.blocking_write()))
+            .await
+            .map_err(|e| ProcessorError::InvalidState(format!("File processing task failed: {}", e)))?
+    }
+}
+
+impl Service<LookingGlassQuery> for AsyncMrtProcessor {
+    type Response = LookingGlassResponse;
+    type Error = ProcessorError;
+    type Future = Pin<Box<dyn Future<Output = Result<LookingGlassResponse, ProcessorError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, query: LookingGlassQuery) -> Self::Future {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let processor = inner.read().await;
+            Ok(looking_glass_query(processor.get_current_state(), &query))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::looking_glass::LookingGlassResult;
+    use bgpkit_parser::encoder::MrtUpdatesEncoder;
+    use bgpkit_parser::models::{BgpElem, ElemType, NetworkPrefix};
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_async_processor_service() {
+        let mut encoder = MrtUpdatesEncoder::new();
+        encoder.process_elem(&BgpElem {
+            timestamp: 1600000000.0,
+            elem_type: ElemType::ANNOUNCE,
+            peer_ip: IpAddr::from_str("192.0.2.1").unwrap(),
+            peer_asn: 65001.into(),
+            prefix: NetworkPrefix::from_str("198.51.100.0/24").unwrap(),
+            next_hop: Some(IpAddr::from_str("192.0.2.1").unwrap()),
+            ..Default::default()
+        });
+        let path = std::env::temp_dir().join(format!("mrt_state_to_state_service_{}.mrt", std::process::id()));
+        std::fs::write(&path, encoder.export_bytes()).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut service = AsyncMrtProcessor::new(MrtProcessor::default());
+        let report = runtime.block_on(service.process_update_file(path.clone())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(report.announcements, 1);
+
+        let query = LookingGlassQuery::PrefixLongestMatch(IpAddr::from_str("198.51.100.7").unwrap());
+        let response = runtime.block_on(service.call(query)).unwrap();
+        assert_eq!(response.total_prefixes, 1);
+        assert!(matches!(&response.results[..], [LookingGlassResult::Route { route, .. }] if route.prefix.to_string() == "198.51.100.0/24"));
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::BuildHasher;
+
+use crate::bgp_state::{BgpState, ConnectionState};
+use crate::mrt_processor::BgpPeer;
+
+/// Number of BFS sources used to approximate betweenness centrality on large graphs
+pub const BETWEENNESS_SAMPLE_SIZE: usize = 256;
+
+/// Undirected AS adjacency graph: ASN to the set of neighbouring ASNs
+pub type AsGraph = HashMap<u32, HashSet<u32>>;
+
+/// Build the AS adjacency graph from the AS paths of all established peers.
+///
+/// Prepends are collapsed and paths containing AS sets are skipped, since the order within a set is unknown.
+pub fn build_as_adjacency_graph<S: BuildHasher + Default>(state: &HashMap<BgpPeer, BgpState<S>, S>) -> AsGraph {
+    let mut graph: AsGraph = HashMap::new();
+
+    for peer_state in state.values().filter(|s| *s.connection_state() == ConnectionState::Established) {
+        for ann in peer_state.announcements().values() {
+            let Some(path) = ann.as_path.as_ref().and_then(|path| path.to_u32_vec_opt(true)) else {
+                continue;
+            };
+
+            for pair in path.windows(2) {
+                if pair[0] != pair[1] {
+                    graph.entry(pair[0]).or_default().insert(pair[1]);
+                    graph.entry(pair[1]).or_default().insert(pair[0]);
+                }
+            }
+        }
+    }
+
+    graph
+}
+
+/// Approximate betweenness centrality of every AS, sorted descending by score.
+///
+/// Uses Brandes' algorithm. For graphs with more than `sample_size` nodes only an evenly spaced (deterministic)
+/// sample of sources is used and the scores are scaled up accordingly.
+pub fn betweenness_centrality(graph: &AsGraph, sample_size: usize) -> Vec<(u32, f64)> {
+    let mut nodes: Vec<u32> = graph.keys().copied().collect();
+    nodes.sort_unstable();
+    let index: HashMap<u32, usize> = nodes.iter().enumerate().map(|(i, asn)| (*asn, i)).collect();
+    let neighbours: Vec<Vec<usize>> = nodes.iter().map(|asn| graph[asn].iter().map(|n| index[n]).collect()).collect();
+
+    let n = nodes.len();
+    let sources: Vec<usize> = if n <= sample_size {
+        (0..n).collect()
+    } else {
+        (0..sample_size).map(|i| i * n / sample_size).collect()
+    };
+
+    let mut scores = vec![0.0; n];
+    for &source in &sources {
+        let mut order = Vec::with_capacity(n);
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); n];
+        let mut paths = vec![0.0; n];
+        let mut distance: Vec<Option<usize>> = vec![None; n];
+        paths[source] = 1.0;
+        distance[source] = Some(0);
+
+        let mut queue = VecDeque::from([source]);
+        while let Some(v) = queue.pop_front() {
+            order.push(v);
+            let next = distance[v].unwrap() + 1;
+            for &w in &neighbours[v] {
+                if distance[w].is_none() {
+                    distance[w] = Some(next);
+                    queue.push_back(w);
+                }
+                if distance[w] == Some(next) {
+                    paths[w] += paths[v];
+                    predecessors[w].push(v);
+                }
+            }
+        }
+
+        let mut dependency = vec![0.0; n];
+        for &w in order.iter().rev() {
+            for &v in &predecessors[w] {
+                dependency[v] += paths[v] / paths[w] * (1.0 + dependency[w]);
+            }
+            if w != source {
+                scores[w] += dependency[w];
+            }
+        }
+    }
+
+    // Every shortest path is counted from both ends in an undirected graph
+    let scale = if sources.is_empty() { 0.0 } else { n as f64 / sources.len() as f64 / 2.0 };
+    let mut result: Vec<(u32, f64)> = nodes.into_iter().zip(scores.into_iter().map(|score| score * scale)).collect();
+    result.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(u32, u32)]) -> AsGraph {
+        let mut graph: AsGraph = HashMap::new();
+        for (a, b) in edges {
+            graph.entry(*a).or_default().insert(*b);
+            graph.entry(*b).or_default().insert(*a);
+        }
+        graph
+    }
+
+    #[test]
+    fn test_betweenness_centrality() {
+        // Star around 3356 with a tail behind 65001
+        let g = graph(&[(3356, 65001), (3356, 65002), (3356, 65003), (65001, 65010)]);
+        let ranking = betweenness_centrality(&g, BETWEENNESS_SAMPLE_SIZE);
+
+        assert_eq!(ranking[0], (3356, 5.0));
+        assert_eq!(ranking[1], (65001, 3.0));
+        assert!(ranking[2..].iter().all(|(_, score)| *score == 0.0));
+    }
+}
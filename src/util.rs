@@ -1,8 +1,16 @@
+use core::fmt;
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
-use bgpkit_parser::MrtRecord;
+use bgpkit_parser::models::{Bgp4MpEnum, MrtMessage, NetworkPrefix, TableDumpV2Message};
+use bgpkit_parser::{BgpkitParser, MrtRecord, ParserError};
 use chrono::{DateTime, Utc};
 
+use crate::bgp_state::{Announcement, CommunityKey};
+use crate::error::ProcessorError;
+
 pub fn ip_address_to_v8(ip: IpAddr) -> Vec<u8> {
     match ip {
         IpAddr::V4(ip) => ip.octets().to_vec(),
@@ -22,6 +30,89 @@ pub fn parse_key_value(s: &str) -> Result<(String, String), String>
     Ok((rrc.to_string(), file.to_string()))
 }
 
+/// Format a duration for log messages, e.g. `2h 15m 3s`, `45s` or `300ms`.
+///
+/// Days are only split off for durations longer than 30 days.
+pub fn format_duration(d: chrono::Duration) -> String {
+    if d < chrono::Duration::zero() {
+        return "<negative>".to_string();
+    }
+    if d.is_zero() {
+        return "0s".to_string();
+    }
+    if d < chrono::Duration::seconds(1) {
+        return format!("{}ms", d.num_milliseconds());
+    }
+
+    let total_seconds = d.num_seconds();
+    let (days, hours) = if d > chrono::Duration::days(30) {
+        (total_seconds / 86400, (total_seconds % 86400) / 3600)
+    } else {
+        (0, total_seconds / 3600)
+    };
+    let minutes = (total_seconds % 3600) / 60;
+    let seconds = total_seconds % 60;
+
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m {seconds}s")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m {seconds}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// Prefix in CIDR notation, e.g. `192.0.2.0/24`, without the ADD-PATH path id
+pub fn format_prefix(prefix: &NetworkPrefix) -> String {
+    prefix.prefix.to_string()
+}
+
+/// Canonical ordering of prefixes: IPv4 before IPv6, then by address and then by prefix length.
+///
+/// Returns (AFI, address left-aligned in 16 bytes, prefix length).
+pub fn prefix_sort_key(prefix: &NetworkPrefix) -> (u8, [u8; 16], u8) {
+    let mut address = [0u8; 16];
+    let afi = match prefix.prefix.addr() {
+        IpAddr::V4(addr) => {
+            address[..4].copy_from_slice(&addr.octets());
+            1
+        },
+        IpAddr::V6(addr) => {
+            address = addr.octets();
+            2
+        },
+    };
+    (afi, address, prefix.prefix.prefix_len())
+}
+
+/// Zero the host bits of a prefix, e.g. `192.0.2.1/24` becomes `192.0.2.0/24` (RFC 4271 §4.3)
+pub fn normalize_prefix(prefix: NetworkPrefix) -> NetworkPrefix {
+    NetworkPrefix {
+        prefix: prefix.prefix.trunc(),
+        path_id: prefix.path_id,
+    }
+}
+
+/// Hash of the route attributes of an announcement: next hop, AS path, communities, origin, local pref and MED.
+///
+/// Equal attributes give equal hashes, equal hashes do not guarantee equal attributes.
+pub fn attribute_hash(ann: &Announcement) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (ann.next_hop, &ann.as_path, ann.origin, ann.local_pref, ann.med).hash(&mut hasher);
+    // `MetaCommunity` does not implement Hash
+    ann.communities.as_ref().map(Vec::len).hash(&mut hasher);
+    for community in ann.communities.iter().flatten() {
+        CommunityKey(*community).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Timestamp of an MRT record.
+///
+/// Extended timestamp records (BGP4MP_ET, RFC 6396 §3) carry microseconds after the common header, bgpkit_parser
+/// parses them into the same `Bgp4Mp` message with `microsecond_timestamp` set.
 pub fn mrt_record_ts(record: &MrtRecord) -> DateTime<Utc> {
     match record.common_header.microsecond_timestamp {
         None => DateTime::from_timestamp(record.common_header.timestamp as i64, 0).unwrap(),
@@ -29,6 +120,210 @@ pub fn mrt_record_ts(record: &MrtRecord) -> DateTime<Utc> {
     }
 }
 
+/// Serialize a `HashMap` as a sequence of `(key, value)` pairs, for keys that can not be map keys in the output format
+pub mod map_as_pairs {
+    use std::collections::HashMap;
+    use std::hash::{BuildHasher, Hash};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<K: Serialize, V: Serialize, H, S: Serializer>(map: &HashMap<K, V, H>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, H, D>(deserializer: D) -> Result<HashMap<K, V, H>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        H: BuildHasher + Default,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// Timestamp of the first record in an MRT file, `None` for an empty file
+pub fn first_record_timestamp<P: AsRef<Path>>(path: P) -> Result<Option<DateTime<Utc>>, ProcessorError> {
+    let parser = BgpkitParser::new(path.as_ref().to_str().unwrap())?;
+    Ok(parser.into_record_iter().next().map(|record| mrt_record_ts(&record)))
+}
+
+/// Kind of records in an MRT file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    /// BGP4MP messages and state changes
+    Updates,
+    /// TABLE_DUMP or TABLE_DUMP_V2 records (bview/RIB)
+    TableDump,
+    /// Both updates and table dump records
+    Mixed,
+}
+
+/// Kind of records in an MRT file, judged by its first record
+pub fn detect_mrt_file_type<P: AsRef<Path>>(path: P) -> Result<FileType, ProcessorError> {
+    let parser = BgpkitParser::new(path.as_ref().to_str().unwrap())?;
+    match parser.into_record_iter().next().map(|record| record.message) {
+        Some(MrtMessage::Bgp4Mp(_)) => Ok(FileType::Updates),
+        Some(MrtMessage::TableDumpMessage(_) | MrtMessage::TableDumpV2Message(_)) => Ok(FileType::TableDump),
+        None => Err(ProcessorError::UnsupportedFileType {
+            path: path.as_ref().display().to_string(),
+            reason: "file does not contain any MRT records".to_string(),
+        }),
+    }
+}
+
+/// Whether a file name matches a pattern where `*` matches any sequence of characters and `?` a single character
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let (name, pattern): (Vec<char>, Vec<char>) = (name.chars().collect(), pattern.chars().collect());
+    // Position in the pattern of the last `*` and the position in the name it was matched from
+    let (mut n, mut p, mut star): (usize, usize, Option<(usize, usize)>) = (0, 0, None);
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            n += 1;
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` match one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Files in `dir` with a name matching `pattern`, sorted lexicographically.
+///
+/// For the standard RIS and RouteViews file names this is chronological order.
+pub fn list_mrt_files<P: AsRef<Path>>(dir: P, pattern: &str) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && entry.file_name().to_str().is_some_and(|name| matches_pattern(name, pattern)) {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Compression of an MRT file, derived from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Lz4,
+}
+
+impl CompressionType {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => CompressionType::Gzip,
+            Some("bz2") => CompressionType::Bzip2,
+            Some("xz") => CompressionType::Xz,
+            Some("lz4") => CompressionType::Lz4,
+            _ => CompressionType::None,
+        }
+    }
+}
+
+/// Summary of a fully parsed MRT file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MrtFileInfo {
+    pub record_count: u64,
+    pub first_ts: DateTime<Utc>,
+    pub last_ts: DateTime<Utc>,
+    /// Number of distinct (address, ASN) peers
+    pub peer_count: usize,
+    pub file_type: FileType,
+    pub compression: CompressionType,
+}
+
+/// An MRT file that could not be parsed completely
+#[derive(Debug)]
+pub struct ValidationError {
+    pub path: String,
+    /// Number of records parsed successfully before the failure
+    pub records_parsed: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: invalid after {} records: {}", self.path, self.records_parsed, self.reason)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Parse a complete MRT file to check that it is not corrupted or truncated
+pub fn validate_mrt_file<P: AsRef<Path>>(path: P) -> Result<MrtFileInfo, ValidationError> {
+    let path_str = path.as_ref().display().to_string();
+    let error = |records_parsed: u64, reason: String| ValidationError { path: path_str.clone(), records_parsed, reason };
+
+    let mut parser = BgpkitParser::new(path.as_ref().to_str().unwrap()).map_err(|e| error(0, e.to_string()))?;
+
+    let mut record_count = 0;
+    let mut timestamps: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    let mut peers: HashSet<(IpAddr, u32)> = HashSet::new();
+    let (mut has_updates, mut has_table_dump) = (false, false);
+
+    loop {
+        let record = match parser.next_record() {
+            Ok(record) => record,
+            Err(e) if matches!(e.error, ParserError::EofExpected) => break,
+            Err(e) => return Err(error(record_count, e.error.to_string())),
+        };
+
+        record_count += 1;
+        let ts = mrt_record_ts(&record);
+        timestamps = Some(timestamps.map_or((ts, ts), |(first, last)| (first.min(ts), last.max(ts))));
+
+        match record.message {
+            MrtMessage::Bgp4Mp(Bgp4MpEnum::Message(msg)) => {
+                has_updates = true;
+                peers.insert((msg.peer_ip, msg.peer_asn.to_u32()));
+            },
+            MrtMessage::Bgp4Mp(Bgp4MpEnum::StateChange(msg)) => {
+                has_updates = true;
+                peers.insert((msg.peer_addr, msg.peer_asn.to_u32()));
+            },
+            MrtMessage::TableDumpMessage(msg) => {
+                has_table_dump = true;
+                peers.insert((msg.peer_address, msg.peer_asn.to_u32()));
+            },
+            MrtMessage::TableDumpV2Message(TableDumpV2Message::PeerIndexTable(table)) => {
+                has_table_dump = true;
+                peers.extend(table.id_peer_map.values().map(|peer| (peer.peer_address, peer.peer_asn.to_u32())));
+            },
+            MrtMessage::TableDumpV2Message(_) => has_table_dump = true,
+        }
+    }
+
+    let (first_ts, last_ts) = timestamps.ok_or_else(|| error(0, "file does not contain any MRT records".to_string()))?;
+    let file_type = match (has_updates, has_table_dump) {
+        (true, true) => FileType::Mixed,
+        (false, true) => FileType::TableDump,
+        _ => FileType::Updates,
+    };
+
+    Ok(MrtFileInfo {
+        record_count,
+        first_ts,
+        last_ts,
+        peer_count: peers.len(),
+        file_type,
+        compression: CompressionType::from_path(&path),
+    })
+}
+
 /// Extension trait for DateTime<Utc> that adds conversion to f64 timestamp
 pub trait DateTimeExt {
     /// Convert to seconds since epoch as f64
@@ -44,3 +339,50 @@ impl DateTimeExt for DateTime<Utc> {
 
 // Then use it like:
 // let timestamp = some_datetime.to_timestamp_f64();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("updates.20250320.0740.gz", "*.gz"));
+        assert!(matches_pattern("updates.20250320.0740.gz", "updates.*.07?0.gz"));
+        assert!(matches_pattern("a.gz.gz", "*.gz"));
+        assert!(!matches_pattern("updates.20250320.0740.bz2", "*.gz"));
+        assert!(!matches_pattern("bview.gz", "updates*"));
+        assert!(matches_pattern("", "*"));
+    }
+
+    #[test]
+    fn test_prefix_sort_key() {
+        let key = |prefix: &str| prefix_sort_key(&NetworkPrefix::from_str(prefix).unwrap());
+        assert!(key("255.255.255.0/24") < key("::/0"));
+        assert!(key("10.0.0.0/8") < key("10.0.0.0/24"));
+        assert!(key("10.0.0.0/24") < key("10.0.1.0/24"));
+        assert!(key("9.0.0.0/8") < key("10.0.0.0/8"));
+        assert_eq!(format_prefix(&NetworkPrefix::from_str("192.0.2.0/24").unwrap()), "192.0.2.0/24");
+    }
+
+    #[test]
+    fn test_normalize_prefix() {
+        let normalize = |prefix: &str| normalize_prefix(NetworkPrefix::from_str(prefix).unwrap()).to_string();
+        assert_eq!(normalize("192.0.2.1/24"), "192.0.2.0/24");
+        assert_eq!(normalize("192.0.2.0/24"), "192.0.2.0/24");
+        assert_eq!(normalize("2001:db8:1:ff::1/48"), "2001:db8:1::/48");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::zero()), "0s");
+        assert_eq!(format_duration(Duration::milliseconds(300)), "300ms");
+        assert_eq!(format_duration(Duration::seconds(45)), "45s");
+        assert_eq!(format_duration(Duration::hours(1)), "1h 0m 0s");
+        assert_eq!(format_duration(Duration::seconds(2 * 3600 + 15 * 60 + 3)), "2h 15m 3s");
+        assert_eq!(format_duration(Duration::days(2)), "48h 0m 0s");
+        assert_eq!(format_duration(Duration::days(31) + Duration::minutes(5)), "31d 0h 5m 0s");
+        assert_eq!(format_duration(Duration::seconds(-1)), "<negative>");
+    }
+}
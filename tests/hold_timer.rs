@@ -0,0 +1,35 @@
+use chrono::{DateTime, Duration};
+use mrt_state_to_state::bgp_state::{BgpState, ConnectionState};
+
+fn established_state(last_message: i64) -> BgpState {
+    let mut state = BgpState::new();
+    state.update_connection_state(DateTime::from_timestamp(last_message - 10, 0).unwrap(), ConnectionState::Established);
+    state.update_last_message_timestamp(DateTime::from_timestamp(last_message, 0).unwrap());
+    state
+}
+
+#[test]
+fn test_not_expired_within_hold_time() {
+    let state = established_state(1600000000);
+    let hold_time = Duration::seconds(180);
+
+    assert!(!state.hold_timer_expired(DateTime::from_timestamp(1600000000, 0).unwrap(), hold_time));
+    assert!(!state.hold_timer_expired(DateTime::from_timestamp(1600000090, 0).unwrap(), hold_time));
+    // Silent for exactly the hold time is not yet expired
+    assert!(!state.hold_timer_expired(DateTime::from_timestamp(1600000180, 0).unwrap(), hold_time));
+}
+
+#[test]
+fn test_expired_after_hold_time() {
+    let state = established_state(1600000000);
+    let hold_time = Duration::seconds(180);
+
+    assert!(state.hold_timer_expired(DateTime::from_timestamp(1600000181, 0).unwrap(), hold_time));
+    assert!(state.hold_timer_expired(DateTime::from_timestamp(1600003600, 0).unwrap(), hold_time));
+}
+
+#[test]
+fn test_no_messages_never_expire() {
+    let state = BgpState::new();
+    assert!(!state.hold_timer_expired(DateTime::from_timestamp(1600003600, 0).unwrap(), Duration::seconds(180)));
+}